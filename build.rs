@@ -0,0 +1,22 @@
+use std::process::Command;
+
+// Rebuild when a migration is added so `sqlx::migrate!` embeds it.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+
+    // Reported by `/api/admin/health`. Builds without a checkout (the Docker
+    // image ignores `.git`) can pass `GIT_COMMIT` instead.
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if std::env::var_os("GIT_COMMIT").is_none() {
+        let commit = Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok());
+        if let Some(commit) = commit {
+            println!("cargo:rustc-env=GIT_COMMIT={}", commit.trim());
+        }
+    }
+}
@@ -1,12 +1,12 @@
 "use client"
 
 import React from "react"
-import Link from "next/link"
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card"
+import { Button } from "@/components/ui/button"
 import { usePosts } from "@/hooks/useApi"
 
 export default function Blog() {
-  const { posts, loading, error } = usePosts();
+  const { posts, loading, error, hasMore, loadMore, loadingMore } = usePosts();
 
   if (loading) {
     return (
@@ -72,12 +72,13 @@ export default function Blog() {
               <Card key={post.id} className="transition-all hover:shadow-lg hover:scale-[1.02]">
                 <CardHeader>
                   <CardTitle className="text-xl">
-                    <Link 
-                      href={`/blog/${post.id}`}
+                    {/* A full page load, since posts are rendered by the server. */}
+                    <a
+                      href={`/blog/${post.slug}`}
                       className="hover:text-primary transition-colors"
                     >
                       {post.title}
-                    </Link>
+                    </a>
                   </CardTitle>
                   <div className="flex items-center gap-4 text-sm text-muted-foreground">
                     <span>Published: {new Date(post.published_date).toLocaleDateString()}</span>
@@ -91,6 +92,11 @@ export default function Blog() {
                 </CardContent>
               </Card>
             ))}
+            {hasMore && (
+              <Button variant="outline" className="w-full" onClick={loadMore} disabled={loadingMore}>
+                {loadingMore ? "Loading..." : "Load more posts"}
+              </Button>
+            )}
           </div>
         )}
       </div>
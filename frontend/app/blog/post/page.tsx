@@ -0,0 +1,32 @@
+import React from "react"
+
+// The server renders every post into this page at /blog/{slug}, filling the
+// elements below by id, so the export doesn't need to know which posts exist.
+const empty = { __html: "" }
+
+export default function BlogPostPage() {
+  return (
+    <div className="container mx-auto max-w-4xl px-4 py-8">
+      <article className="prose prose-neutral dark:prose-invert max-w-none">
+        <h1
+          id="post-title"
+          className="text-3xl font-bold mb-2"
+          dangerouslySetInnerHTML={empty}
+          suppressHydrationWarning
+        />
+        <div
+          id="post-meta"
+          className="flex items-center gap-4 text-sm text-muted-foreground mb-6"
+          dangerouslySetInnerHTML={empty}
+          suppressHydrationWarning
+        />
+        <div
+          id="post-body"
+          className="post-content"
+          dangerouslySetInnerHTML={empty}
+          suppressHydrationWarning
+        />
+      </article>
+    </div>
+  );
+}
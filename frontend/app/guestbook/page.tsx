@@ -1,14 +1,38 @@
 "use client"
 
-import React, { useState } from "react"
+import React, { useEffect, useState } from "react"
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card"
 import { Button } from "@/components/ui/button"
 import { useComments } from "@/hooks/useApi"
 
 export default function Guestbook() {
-  const { comments, loading, error, refetch } = useComments();
+  const { comments, setComments, loading, error, refetch, hasMore, loadMore, loadingMore } = useComments();
   const [isSubmitting, setIsSubmitting] = useState(false);
 
+  // Live updates. `reset` means we fell too far behind to replay, so the
+  // list is fetched again; the browser reconnects on its own after errors.
+  useEffect(() => {
+    const stream = new EventSource('/api/comments/stream');
+    const upsert = (event: MessageEvent) => {
+      const comment = JSON.parse(event.data);
+      setComments((current) => {
+        const index = current.findIndex((existing) => existing.id === comment.id);
+        if (index === -1) return [comment, ...current];
+        const next = [...current];
+        next[index] = comment;
+        return next;
+      });
+    };
+    stream.addEventListener('created', upsert);
+    stream.addEventListener('edited', upsert);
+    stream.addEventListener('deleted', (event) => {
+      const { id } = JSON.parse((event as MessageEvent).data);
+      setComments((current) => current.filter((comment) => comment.id !== id));
+    });
+    stream.addEventListener('reset', () => refetch());
+    return () => stream.close();
+  }, []);
+
   const handleGithubSignIn = () => {
     window.location.href = '/api/auth/github';
   };
@@ -113,8 +137,8 @@ export default function Guestbook() {
             </Card>
           ) : (
             <div className="space-y-4">
-              {comments.map((comment, index) => (
-                <Card key={index}>
+              {comments.map((comment) => (
+                <Card key={comment.id}>
                   <CardContent className="pt-6">
                     <div className="flex items-center gap-3 mb-3">
                       <div className="w-8 h-8 bg-primary/10 rounded-full flex items-center justify-center">
@@ -131,6 +155,11 @@ export default function Guestbook() {
                   </CardContent>
                 </Card>
               ))}
+              {hasMore && (
+                <Button variant="outline" className="w-full" onClick={loadMore} disabled={loadingMore}>
+                  {loadingMore ? "Loading..." : "Load older comments"}
+                </Button>
+              )}
             </div>
           )}
         </div>
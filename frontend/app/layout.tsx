@@ -3,6 +3,7 @@ import { Inter, JetBrains_Mono } from "next/font/google";
 import "./globals.css";
 
 import { Header } from "@/components/layout/header";
+import { PageviewTracker } from "@/components/pageview-tracker";
 import { ThemeProvider } from "@/hooks/useTheme";
 
 const inter = Inter({
@@ -36,6 +37,7 @@ export default function RootLayout({
     <html lang="en" suppressHydrationWarning>
       <body className={`${inter.variable} ${jetbrainsMono.variable} antialiased`}>
         <ThemeProvider defaultTheme="system" storageKey="theme">
+          <PageviewTracker />
           <div className="min-h-screen flex flex-col">
             <Header />
             <main className="flex-1">
@@ -0,0 +1,32 @@
+"use client"
+
+import { useEffect, useRef } from "react"
+import { usePathname } from "next/navigation"
+
+// Records a pageview for first-party analytics on every navigation. Only the
+// landing page carries the external referrer; later navigations happen
+// inside the app.
+export function PageviewTracker() {
+  const pathname = usePathname()
+  const referrer = useRef<string | null>(null)
+  const landed = useRef(false)
+
+  useEffect(() => {
+    if (!landed.current) {
+      landed.current = true
+      referrer.current = document.referrer || null
+    }
+    fetch("/api/analytics/pageview", {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({
+        path: window.location.pathname + window.location.search,
+        referrer: referrer.current,
+      }),
+      keepalive: true,
+    }).catch((error) => console.error(error))
+    referrer.current = null
+  }, [pathname])
+
+  return null
+}
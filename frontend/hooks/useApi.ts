@@ -4,6 +4,61 @@ import { useState, useEffect } from "react"
 
 const API_BASE = process.env.NODE_ENV === 'production' ? '' : 'http://localhost:8080'
 
+// List endpoints return one page at a time as `{ items, next, prev }`, where
+// `next` is the URL of the following page or null on the last one.
+async function fetchPage<T>(url: string, errorMessage: string): Promise<{ items: T[], next: string | null }> {
+  const response = await fetch(`${API_BASE}${url}`)
+  if (!response.ok) throw new Error(errorMessage)
+  return response.json()
+}
+
+// Loads the first page of a list and appends the next one on `loadMore`.
+function usePagedList<T>(path: string, errorMessage: string) {
+  const [items, setItems] = useState<T[]>([])
+  const [next, setNext] = useState<string | null>(null)
+  const [loading, setLoading] = useState(true)
+  const [loadingMore, setLoadingMore] = useState(false)
+  const [error, setError] = useState<string | null>(null)
+
+  const fetchFirstPage = async () => {
+    try {
+      const page = await fetchPage<T>(path, errorMessage)
+      setItems(page.items)
+      setNext(page.next)
+    } catch (err) {
+      setError(err instanceof Error ? err.message : 'Unknown error')
+    } finally {
+      setLoading(false)
+    }
+  }
+
+  useEffect(() => {
+    fetchFirstPage()
+  }, [])
+
+  const refetch = () => {
+    setLoading(true)
+    setError(null)
+    fetchFirstPage()
+  }
+
+  const loadMore = async () => {
+    if (!next || loadingMore) return
+    setLoadingMore(true)
+    try {
+      const page = await fetchPage<T>(next, errorMessage)
+      setItems((current) => [...current, ...page.items])
+      setNext(page.next)
+    } catch (err) {
+      setError(err instanceof Error ? err.message : 'Unknown error')
+    } finally {
+      setLoadingMore(false)
+    }
+  }
+
+  return { items, setItems, loading, loadingMore, error, hasMore: next !== null, loadMore, refetch }
+}
+
 export function useGithubStars() {
   const [stars, setStars] = useState<number | null>(null)
   const [loading, setLoading] = useState(true)
@@ -37,9 +92,8 @@ export function useBlogViews() {
   useEffect(() => {
     const fetchViews = async () => {
       try {
-        const response = await fetch(`${API_BASE}/api/posts`)
-        if (!response.ok) throw new Error('Failed to fetch blog posts')
-        const posts = await response.json()
+        // The largest page; the site has far fewer posts than that.
+        const { items: posts } = await fetchPage<any>('/api/posts?limit=100', 'Failed to fetch blog posts')
         const totalViews = posts.reduce((sum: number, post: any) => sum + (post.views || 0), 0)
         setViews(totalViews)
       } catch (err) {
@@ -63,11 +117,11 @@ export function useRecentGuest() {
   useEffect(() => {
     const fetchRecentGuest = async () => {
       try {
-        const response = await fetch(`${API_BASE}/api/comments`)
+        const response = await fetch(`${API_BASE}/api/comments?limit=1`)
         if (!response.ok) throw new Error('Failed to fetch comments')
-        const comments = await response.json()
-        if (comments.length > 0) {
-          setGuest(comments[0].name)
+        const { items } = await response.json()
+        if (items.length > 0) {
+          setGuest(items[0].name)
         }
       } catch (err) {
         setError(err instanceof Error ? err.message : 'Unknown error')
@@ -83,65 +137,13 @@ export function useRecentGuest() {
 }
 
 export function usePosts() {
-  const [posts, setPosts] = useState<any[]>([])
-  const [loading, setLoading] = useState(true)
-  const [error, setError] = useState<string | null>(null)
-
-  const fetchPosts = async () => {
-    try {
-      const response = await fetch(`${API_BASE}/api/posts`)
-      if (!response.ok) throw new Error('Failed to fetch posts')
-      const data = await response.json()
-      setPosts(data)
-    } catch (err) {
-      setError(err instanceof Error ? err.message : 'Unknown error')
-    } finally {
-      setLoading(false)
-    }
-  }
-
-  useEffect(() => {
-    fetchPosts()
-  }, [])
-
-  const refetch = () => {
-    setLoading(true)
-    setError(null)
-    fetchPosts()
-  }
-
-  return { posts, loading, error, refetch }
+  const { items, ...list } = usePagedList<any>('/api/posts', 'Failed to fetch posts')
+  return { posts: items, ...list }
 }
 
 export function useComments() {
-  const [comments, setComments] = useState<any[]>([])
-  const [loading, setLoading] = useState(true)
-  const [error, setError] = useState<string | null>(null)
-
-  const fetchComments = async () => {
-    try {
-      const response = await fetch(`${API_BASE}/api/comments`)
-      if (!response.ok) throw new Error('Failed to fetch comments')
-      const data = await response.json()
-      setComments(data)
-    } catch (err) {
-      setError(err instanceof Error ? err.message : 'Unknown error')
-    } finally {
-      setLoading(false)
-    }
-  }
-
-  useEffect(() => {
-    fetchComments()
-  }, [])
-
-  const refetch = () => {
-    setLoading(true)
-    setError(null)
-    fetchComments()
-  }
-
-  return { comments, loading, error, refetch }
+  const { items, setItems, ...list } = usePagedList<any>('/api/comments', 'Failed to fetch comments')
+  return { comments: items, setComments: setItems, ...list }
 }
 
 export function usePost(id: string) {
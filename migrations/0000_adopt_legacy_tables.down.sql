@@ -0,0 +1,3 @@
+-- Adopting the legacy tables only adds what 0001 and 0003 would have
+-- created; reverting those migrations removes it.
+SELECT 1;
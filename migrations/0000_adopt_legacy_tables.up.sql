@@ -0,0 +1,78 @@
+-- Databases created from the old readme snippets already have `posts` and
+-- `comments` with only their original columns, so the CREATE TABLE IF NOT
+-- EXISTS statements in 0001 and 0003 leave them alone. This brings both up to
+-- the shape those migrations create, before their indexes and triggers refer
+-- to the new columns. On a fresh database, or one created by 0001 and 0003,
+-- it does nothing.
+DO $$
+DECLARE
+    post RECORD;
+    base TEXT;
+    candidate TEXT;
+    suffix INTEGER;
+BEGIN
+    IF to_regclass('posts') IS NOT NULL AND NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'posts' AND column_name = 'slug'
+    ) THEN
+        CREATE TABLE IF NOT EXISTS series (
+            id SERIAL PRIMARY KEY,
+            slug VARCHAR(100) UNIQUE NOT NULL,
+            name VARCHAR(100) NOT NULL,
+            description TEXT);
+
+        ALTER TABLE posts
+            ADD COLUMN IF NOT EXISTS slug VARCHAR(200),
+            ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'published',
+            ADD COLUMN IF NOT EXISTS publish_at TIMESTAMP WITH TIME ZONE,
+            ADD COLUMN IF NOT EXISTS search_vector tsvector,
+            ADD COLUMN IF NOT EXISTS series_id INTEGER REFERENCES series(id) ON DELETE SET NULL,
+            ADD COLUMN IF NOT EXISTS series_position INTEGER;
+
+        -- Same rules as `slugs::generate_slug`: the slugified title, prefixed
+        -- when it has no letters, with `-2`, `-3`, … on collision.
+        FOR post IN SELECT id, title FROM posts WHERE slug IS NULL ORDER BY id LOOP
+            base := left(trim(both '-' from regexp_replace(lower(post.title), '[^[:alnum:]]+', '-', 'g')), 192);
+            base := rtrim(base, '-');
+            IF base !~ '[^0-9-]' THEN
+                base := rtrim('post-' || base, '-');
+            END IF;
+            candidate := base;
+            suffix := 2;
+            WHILE EXISTS (SELECT 1 FROM posts WHERE slug = candidate) LOOP
+                candidate := base || '-' || suffix;
+                suffix := suffix + 1;
+            END LOOP;
+            UPDATE posts SET slug = candidate WHERE id = post.id;
+        END LOOP;
+
+        -- The body is added when the post files are imported as revisions.
+        UPDATE posts SET search_vector = setweight(to_tsvector('english', title), 'A');
+
+        ALTER TABLE posts
+            ALTER COLUMN slug SET NOT NULL,
+            ADD CONSTRAINT posts_slug_key UNIQUE (slug),
+            ADD CONSTRAINT posts_status_check
+                CHECK (status IN ('draft', 'scheduled', 'published', 'unlisted', 'archived')),
+            ADD CONSTRAINT posts_check CHECK (status <> 'scheduled' OR publish_at IS NOT NULL),
+            ADD CONSTRAINT posts_series_id_series_position_key UNIQUE (series_id, series_position);
+    END IF;
+
+    IF to_regclass('comments') IS NOT NULL AND NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'comments' AND column_name = 'post_id'
+    ) THEN
+        -- Existing comments are guestbook entries that were already shown.
+        ALTER TABLE comments
+            ADD COLUMN IF NOT EXISTS post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE,
+            ADD COLUMN IF NOT EXISTS parent_id INTEGER REFERENCES comments(id) ON DELETE CASCADE,
+            ADD COLUMN IF NOT EXISTS depth INTEGER NOT NULL DEFAULT 0,
+            ADD COLUMN IF NOT EXISTS edited_at TIMESTAMP WITH TIME ZONE,
+            ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP WITH TIME ZONE,
+            ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'approved'
+                CONSTRAINT comments_status_check
+                CHECK (status IN ('pending', 'approved', 'flagged', 'rejected')),
+            ADD COLUMN IF NOT EXISTS search_vector tsvector GENERATED ALWAYS AS
+                (to_tsvector('english', coalesce(name, '') || ' ' || coalesce(comment, ''))) STORED;
+    END IF;
+END $$;
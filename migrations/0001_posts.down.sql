@@ -0,0 +1,8 @@
+DROP TABLE IF EXISTS post_categories;
+DROP TABLE IF EXISTS categories;
+DROP TABLE IF EXISTS post_tags;
+DROP TABLE IF EXISTS tags;
+DROP TABLE IF EXISTS post_revisions;
+DROP TABLE IF EXISTS post_slug_history;
+DROP TABLE IF EXISTS posts;
+DROP TABLE IF EXISTS series;
@@ -0,0 +1,59 @@
+-- Posts, their slug history and revisions, and taxonomy. Every statement is
+-- guarded so databases created from the old readme snippets are adopted as-is.
+CREATE TABLE IF NOT EXISTS series (
+    id SERIAL PRIMARY KEY,
+    slug VARCHAR(100) UNIQUE NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    description TEXT);
+
+CREATE TABLE IF NOT EXISTS posts (
+    id SERIAL PRIMARY KEY,
+    title TEXT NOT NULL,
+    slug VARCHAR(200) UNIQUE NOT NULL,
+    published_date DATE NOT NULL,
+    views INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'published'
+        CHECK (status IN ('draft', 'scheduled', 'published', 'unlisted', 'archived')),
+    publish_at TIMESTAMP WITH TIME ZONE,
+    search_vector tsvector,
+    series_id INTEGER REFERENCES series(id) ON DELETE SET NULL,
+    series_position INTEGER,
+    CHECK (status <> 'scheduled' OR publish_at IS NOT NULL),
+    UNIQUE (series_id, series_position));
+CREATE INDEX IF NOT EXISTS posts_search_idx ON posts USING GIN (search_vector);
+
+CREATE TABLE IF NOT EXISTS post_slug_history (
+    slug VARCHAR(200) PRIMARY KEY,
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP);
+
+CREATE TABLE IF NOT EXISTS post_revisions (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    rendered TEXT NOT NULL,
+    toc TEXT NOT NULL DEFAULT '',
+    plain_text TEXT NOT NULL DEFAULT '',
+    author_id VARCHAR(255),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP);
+CREATE INDEX IF NOT EXISTS post_revisions_post_id_idx ON post_revisions (post_id, id DESC);
+
+CREATE TABLE IF NOT EXISTS tags (
+    id SERIAL PRIMARY KEY,
+    slug VARCHAR(100) UNIQUE NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    description TEXT);
+CREATE TABLE IF NOT EXISTS post_tags (
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (post_id, tag_id));
+
+CREATE TABLE IF NOT EXISTS categories (
+    id SERIAL PRIMARY KEY,
+    slug VARCHAR(100) UNIQUE NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    description TEXT);
+CREATE TABLE IF NOT EXISTS post_categories (
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    category_id INTEGER NOT NULL REFERENCES categories(id) ON DELETE CASCADE,
+    PRIMARY KEY (post_id, category_id));
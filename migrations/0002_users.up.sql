@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS users (
+    github_id VARCHAR(255) PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    roles TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_login TIMESTAMP WITH TIME ZONE);
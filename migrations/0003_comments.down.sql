@@ -0,0 +1,8 @@
+DROP TRIGGER IF EXISTS comments_notify ON comments;
+DROP FUNCTION IF EXISTS notify_comment_event();
+DROP TABLE IF EXISTS comment_events;
+DROP TABLE IF EXISTS spam_tokens;
+DROP TABLE IF EXISTS spam_training;
+DROP TABLE IF EXISTS comment_spam_verdicts;
+DROP TABLE IF EXISTS comment_moderation_log;
+DROP TABLE IF EXISTS comments;
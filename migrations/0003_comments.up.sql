@@ -0,0 +1,73 @@
+-- Guestbook and post comments with moderation, spam filtering and the event
+-- log behind /api/comments/stream.
+CREATE TABLE IF NOT EXISTS comments (
+    id SERIAL PRIMARY KEY,
+    userid VARCHAR(255),
+    name VARCHAR(255),
+    comment TEXT,
+    timestamp TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE,
+    parent_id INTEGER REFERENCES comments(id) ON DELETE CASCADE,
+    depth INTEGER NOT NULL DEFAULT 0,
+    edited_at TIMESTAMP WITH TIME ZONE,
+    deleted_at TIMESTAMP WITH TIME ZONE,
+    status TEXT NOT NULL DEFAULT 'approved'
+        CHECK (status IN ('pending', 'approved', 'flagged', 'rejected')),
+    search_vector tsvector GENERATED ALWAYS AS
+        (to_tsvector('english', coalesce(name, '') || ' ' || coalesce(comment, ''))) STORED);
+CREATE INDEX IF NOT EXISTS comments_search_idx ON comments USING GIN (search_vector);
+CREATE INDEX IF NOT EXISTS comments_post_id_idx ON comments (post_id, timestamp);
+CREATE INDEX IF NOT EXISTS comments_status_idx ON comments (status)
+    WHERE status IN ('pending', 'flagged');
+
+CREATE TABLE IF NOT EXISTS comment_moderation_log (
+    id SERIAL PRIMARY KEY,
+    comment_id INTEGER NOT NULL,
+    moderator_id VARCHAR(255) NOT NULL,
+    action TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP);
+
+CREATE TABLE IF NOT EXISTS comment_spam_verdicts (
+    comment_id INTEGER PRIMARY KEY REFERENCES comments(id) ON DELETE CASCADE,
+    score REAL NOT NULL,
+    decision TEXT NOT NULL,
+    rules JSONB NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP);
+CREATE TABLE IF NOT EXISTS spam_training (
+    comment_id INTEGER PRIMARY KEY,
+    label TEXT NOT NULL CHECK (label IN ('spam', 'ham')));
+CREATE TABLE IF NOT EXISTS spam_tokens (
+    token TEXT PRIMARY KEY,
+    spam INTEGER NOT NULL DEFAULT 0,
+    ham INTEGER NOT NULL DEFAULT 0);
+
+CREATE TABLE IF NOT EXISTS comment_events (
+    id BIGSERIAL PRIMARY KEY,
+    comment_id INTEGER NOT NULL,
+    post_id INTEGER,
+    kind TEXT NOT NULL CHECK (kind IN ('created', 'edited', 'deleted')),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP);
+
+CREATE OR REPLACE FUNCTION notify_comment_event() RETURNS trigger AS $$
+DECLARE
+    event_id BIGINT;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO comment_events (comment_id, post_id, kind)
+        VALUES (OLD.id, OLD.post_id, 'deleted') RETURNING id INTO event_id;
+    ELSE
+        INSERT INTO comment_events (comment_id, post_id, kind)
+        VALUES (NEW.id, NEW.post_id, CASE
+            WHEN TG_OP = 'INSERT' THEN 'created'
+            WHEN NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN 'deleted'
+            ELSE 'edited' END) RETURNING id INTO event_id;
+    END IF;
+    PERFORM pg_notify('comment_events', event_id::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS comments_notify ON comments;
+CREATE TRIGGER comments_notify
+    AFTER INSERT OR DELETE OR UPDATE OF comment, status, deleted_at ON comments
+    FOR EACH ROW EXECUTE FUNCTION notify_comment_event();
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS analytics_daily;
+DROP TABLE IF EXISTS analytics_hourly;
+DROP TABLE IF EXISTS pageviews;
+DROP TABLE IF EXISTS post_views;
+DROP TABLE IF EXISTS view_salts;
@@ -0,0 +1,42 @@
+-- Unique post views and first-party pageview analytics. Visitors are only
+-- ever stored as hashes salted with a key that rotates daily.
+CREATE TABLE IF NOT EXISTS view_salts (
+    day DATE PRIMARY KEY,
+    salt BYTEA NOT NULL);
+
+CREATE TABLE IF NOT EXISTS post_views (
+    id BIGSERIAL PRIMARY KEY,
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    visitor_hash CHAR(64) NOT NULL,
+    viewed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP);
+CREATE INDEX IF NOT EXISTS post_views_visitor_idx
+    ON post_views (post_id, visitor_hash, viewed_at DESC);
+
+CREATE TABLE IF NOT EXISTS pageviews (
+    id BIGSERIAL PRIMARY KEY,
+    path VARCHAR(255) NOT NULL,
+    referrer VARCHAR(255),
+    utm_source VARCHAR(100),
+    utm_medium VARCHAR(100),
+    utm_campaign VARCHAR(100),
+    country CHAR(2),
+    device TEXT NOT NULL,
+    visitor_hash CHAR(64) NOT NULL,
+    viewed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP);
+CREATE INDEX IF NOT EXISTS pageviews_viewed_at_idx ON pageviews (viewed_at);
+
+CREATE TABLE IF NOT EXISTS analytics_hourly (
+    bucket TIMESTAMP WITH TIME ZONE NOT NULL,
+    dimension TEXT NOT NULL,
+    value TEXT NOT NULL,
+    views BIGINT NOT NULL,
+    visitors BIGINT NOT NULL,
+    PRIMARY KEY (bucket, dimension, value));
+CREATE TABLE IF NOT EXISTS analytics_daily (
+    day DATE NOT NULL,
+    dimension TEXT NOT NULL,
+    value TEXT NOT NULL,
+    views BIGINT NOT NULL,
+    visitors BIGINT NOT NULL,
+    PRIMARY KEY (day, dimension, value));
+CREATE INDEX IF NOT EXISTS analytics_daily_dimension_idx ON analytics_daily (dimension, day);
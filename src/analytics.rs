@@ -0,0 +1,351 @@
+use crate::error::ApiError;
+use crate::rate_limit::RateLimiter;
+use crate::state::AppState;
+use crate::users::{AuthenticatedUser, Permission};
+use crate::views;
+use actix_web::http::header::USER_AGENT;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+use std::net::IpAddr;
+
+const MAX_PATH_LENGTH: usize = 255;
+const MAX_FIELD_LENGTH: usize = 100;
+/// Raw pageviews are only kept long enough to be rolled up and re-rolled.
+const PAGEVIEW_RETENTION_DAYS: i32 = 30;
+const HOURLY_RETENTION_DAYS: i32 = 90;
+/// Hourly trends over longer ranges are refused; use daily ones instead.
+const MAX_HOURLY_RANGE_DAYS: u64 = 31;
+const MAX_RANGE_DAYS: u64 = 366;
+const DEFAULT_RANGE_DAYS: u64 = 30;
+const DEFAULT_TOP_LIMIT: i64 = 20;
+
+/// Country lookups from a local MaxMind database, if one is configured.
+pub struct Analytics {
+    geoip: Option<Reader<Vec<u8>>>,
+}
+
+impl Analytics {
+    pub fn new(geoip_database: Option<&str>) -> Result<Analytics, MaxMindDBError> {
+        let geoip = geoip_database.map(Reader::open_readfile).transpose()?;
+        Ok(Analytics { geoip })
+    }
+
+    fn country(&self, ip: IpAddr) -> Option<String> {
+        let record: geoip2::Country = self.geoip.as_ref()?.lookup(ip).ok()?;
+        record.country?.iso_code.map(str::to_string)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Pageview {
+    /// Path and query string of the page, as seen by the browser.
+    pub path: String,
+    /// `document.referrer`; only its host is kept.
+    pub referrer: Option<String>,
+}
+
+/// A breakdown offered by the top-N endpoint. Each maps to a rollup dimension.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    Pages,
+    Referrers,
+    Countries,
+    Devices,
+    UtmSources,
+    UtmMediums,
+    UtmCampaigns,
+}
+
+impl Dimension {
+    fn as_str(self) -> &'static str {
+        match self {
+            Dimension::Pages => "page",
+            Dimension::Referrers => "referrer",
+            Dimension::Countries => "country",
+            Dimension::Devices => "device",
+            Dimension::UtmSources => "utm_source",
+            Dimension::UtmMediums => "utm_medium",
+            Dimension::UtmCampaigns => "utm_campaign",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Hour,
+    #[default]
+    Day,
+}
+
+#[derive(Deserialize)]
+pub struct TopQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct TrendQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    #[serde(default)]
+    pub interval: Interval,
+    /// Restricts the trend to a single page.
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, FromRow)]
+struct TopEntry {
+    /// `null` for direct traffic, unknown countries and untagged campaigns.
+    value: Option<String>,
+    views: i64,
+    visitors: i64,
+}
+
+#[derive(Serialize, FromRow)]
+struct TrendPoint {
+    period: DateTime<Utc>,
+    views: i64,
+    visitors: i64,
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    value.trim().chars().take(max).collect()
+}
+
+/// Coarse device class from the user agent; no fingerprinting beyond this.
+fn device_class(user_agent: &str) -> &'static str {
+    let agent = user_agent.to_lowercase();
+    if agent.contains("ipad")
+        || agent.contains("tablet")
+        || (agent.contains("android") && !agent.contains("mobile"))
+    {
+        "tablet"
+    } else if agent.contains("mobi") || agent.contains("iphone") || agent.contains("android") {
+        "mobile"
+    } else {
+        "desktop"
+    }
+}
+
+/// The referring host, or `None` for direct visits and internal navigation.
+fn referrer_host(referrer: Option<&str>, site_url: &str) -> Option<String> {
+    let host = url::Url::parse(referrer?.trim())
+        .ok()?
+        .host_str()?
+        .to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host).to_string();
+    let own_host = url::Url::parse(site_url).ok().and_then(|url| {
+        url.host_str()
+            .map(|own| own.trim_start_matches("www.").to_lowercase())
+    });
+    (own_host.as_deref() != Some(host.as_str())).then_some(host)
+}
+
+/// Whether the browser asked not to be tracked, via Do Not Track or Global
+/// Privacy Control.
+fn opted_out(req: &HttpRequest) -> bool {
+    ["DNT", "Sec-GPC"]
+        .iter()
+        .any(|name| req.headers().get(*name).is_some_and(|value| value == "1"))
+}
+
+/// Records a pageview from the SPA. Visitors are never tied to their session,
+/// and their address only feeds the daily visitor hash and the country lookup.
+#[post("/analytics/pageview")]
+pub async fn record_pageview(
+    req: HttpRequest,
+    body: web::Json<Pageview>,
+    analytics: web::Data<Analytics>,
+    limiter: web::Data<RateLimiter>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|agent| agent.to_str().ok());
+    let ip = limiter.client_ip(req.headers(), req.peer_addr());
+    let (Some(user_agent), Some(ip)) = (user_agent, ip) else {
+        return Ok(HttpResponse::NoContent().finish());
+    };
+    if opted_out(&req) || views::is_bot(Some(user_agent)) {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    let page = body.path.split('#').next().unwrap_or_default();
+    let (path, query) = page.split_once('?').unwrap_or((page, ""));
+    if !path.starts_with('/') {
+        return Err(ApiError::invalid("Path must start with /"));
+    }
+    let utm = |name: &str| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| truncate(&value, MAX_FIELD_LENGTH))
+            .filter(|value| !value.is_empty())
+    };
+
+    let salt = views::daily_salt(&data.db).await?;
+    sqlx::query(
+        "INSERT INTO pageviews (path, referrer, utm_source, utm_medium, utm_campaign, \
+         country, device, visitor_hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+    .bind(truncate(path, MAX_PATH_LENGTH))
+    .bind(referrer_host(body.referrer.as_deref(), &data.config.site_url))
+    .bind(utm("utm_source"))
+    .bind(utm("utm_medium"))
+    .bind(utm("utm_campaign"))
+    .bind(analytics.country(ip))
+    .bind(device_class(user_agent))
+    .bind(views::visitor_hash(&salt, &format!("{ip}|{user_agent}")))
+    .execute(&data.db)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Re-aggregates recent pageviews into the hourly and daily rollups and drops
+/// expired rows. Recent buckets are recomputed in full, so running this more
+/// often than needed is harmless.
+#[tracing::instrument(skip_all)]
+pub async fn roll_up(db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+    // Every pageview contributes once to the total and once per dimension, so
+    // unique visitors are exact within each bucket and value.
+    const DIMENSIONS: &str = "CROSS JOIN LATERAL (VALUES \
+         ('total', ''), ('page', v.path), ('referrer', COALESCE(v.referrer, '')), \
+         ('country', COALESCE(v.country, '')), ('device', v.device), \
+         ('utm_source', COALESCE(v.utm_source, '')), ('utm_medium', COALESCE(v.utm_medium, '')), \
+         ('utm_campaign', COALESCE(v.utm_campaign, ''))) AS d (dimension, value)";
+
+    let mut tx = db.begin().await?;
+    sqlx::query(&format!(
+        "INSERT INTO analytics_hourly (bucket, dimension, value, views, visitors) \
+         SELECT date_trunc('hour', v.viewed_at), d.dimension, d.value, COUNT(*), \
+                COUNT(DISTINCT v.visitor_hash) \
+         FROM pageviews v {DIMENSIONS} \
+         WHERE v.viewed_at >= date_trunc('hour', now()) - interval '2 hours' \
+         GROUP BY 1, 2, 3 \
+         ON CONFLICT (bucket, dimension, value) \
+         DO UPDATE SET views = EXCLUDED.views, visitors = EXCLUDED.visitors"
+    ))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        "INSERT INTO analytics_daily (day, dimension, value, views, visitors) \
+         SELECT (v.viewed_at AT TIME ZONE 'UTC')::date, d.dimension, d.value, COUNT(*), \
+                COUNT(DISTINCT v.visitor_hash) \
+         FROM pageviews v {DIMENSIONS} \
+         WHERE v.viewed_at >= ((now() AT TIME ZONE 'UTC')::date - 1)::timestamp AT TIME ZONE 'UTC' \
+         GROUP BY 1, 2, 3 \
+         ON CONFLICT (day, dimension, value) \
+         DO UPDATE SET views = EXCLUDED.views, visitors = EXCLUDED.visitors"
+    ))
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM pageviews WHERE viewed_at < now() - make_interval(days => $1)")
+        .bind(PAGEVIEW_RETENTION_DAYS)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM analytics_hourly WHERE bucket < now() - make_interval(days => $1)")
+        .bind(HOURLY_RETENTION_DAYS)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+/// Resolves an inclusive date range, defaulting to the last 30 days and
+/// limited to a year so one request cannot scan every rollup.
+fn date_range(
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<(NaiveDate, NaiveDate), ApiError> {
+    let to = to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = from.unwrap_or(to - Days::new(DEFAULT_RANGE_DAYS - 1));
+    if from > to {
+        return Err(ApiError::invalid("from must not be after to"));
+    }
+    if to - from >= chrono::Duration::days(MAX_RANGE_DAYS as i64) {
+        return Err(ApiError::invalid("Date ranges are limited to 366 days"));
+    }
+    Ok((from, to))
+}
+
+/// Most viewed values of a dimension (pages, referrers, countries, …) over a
+/// date range. Visitors are summed per day, so a reader returning on several
+/// days is counted once for each.
+#[get("/admin/analytics/top/{dimension}")]
+pub async fn top_analytics(
+    user: AuthenticatedUser,
+    path: web::Path<Dimension>,
+    query: web::Query<TopQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ViewAnalytics)?;
+    let (from, to) = date_range(query.from, query.to)?;
+
+    let entries = sqlx::query_as::<_, TopEntry>(
+        "SELECT NULLIF(value, '') AS value, SUM(views)::bigint AS views, \
+                SUM(visitors)::bigint AS visitors \
+         FROM analytics_daily WHERE dimension = $1 AND day BETWEEN $2 AND $3 \
+         GROUP BY value ORDER BY views DESC, value LIMIT $4",
+    )
+    .bind(path.into_inner().as_str())
+    .bind(from)
+    .bind(to)
+    .bind(query.limit.unwrap_or(DEFAULT_TOP_LIMIT).clamp(1, 100))
+    .fetch_all(&data.db)
+    .await?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+/// Views and visitors per hour or day over a date range, with empty periods
+/// filled in as zeros.
+#[get("/admin/analytics/trends")]
+pub async fn analytics_trends(
+    user: AuthenticatedUser,
+    query: web::Query<TrendQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ViewAnalytics)?;
+    let (from, to) = date_range(query.from, query.to)?;
+
+    let (table, column, step) = match query.interval {
+        Interval::Hour => {
+            if to - from >= chrono::Duration::days(MAX_HOURLY_RANGE_DAYS as i64) {
+                return Err(ApiError::invalid("Hourly trends are limited to 31 days"));
+            }
+            ("analytics_hourly", "a.bucket", "1 hour")
+        }
+        Interval::Day => (
+            "analytics_daily",
+            "(a.day::timestamp AT TIME ZONE 'UTC')",
+            "1 day",
+        ),
+    };
+    let (dimension, value) = match &query.path {
+        Some(path) => ("page", path.as_str()),
+        None => ("total", ""),
+    };
+    let start = from.and_time(chrono::NaiveTime::MIN).and_utc();
+    let end = (to + Days::new(1))
+        .and_time(chrono::NaiveTime::MIN)
+        .and_utc();
+
+    let points = sqlx::query_as::<_, TrendPoint>(&format!(
+        "SELECT g AS period, COALESCE(a.views, 0) AS views, COALESCE(a.visitors, 0) AS visitors \
+         FROM generate_series($1::timestamptz, $2::timestamptz - interval '{step}', interval '{step}') g \
+         LEFT JOIN {table} a ON {column} = g AND a.dimension = $3 AND a.value = $4 \
+         ORDER BY g"
+    ))
+    .bind(start)
+    .bind(end)
+    .bind(dimension)
+    .bind(value)
+    .fetch_all(&data.db)
+    .await?;
+    Ok(HttpResponse::Ok().json(points))
+}
@@ -4,8 +4,10 @@ use actix_web::{web, HttpResponse, Responder};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
 use std::collections::HashMap;
+use std::time::Instant;
+use crate::error::ApiError;
 use crate::state::AppState;
-use std::env;
+use crate::users::upsert_user;
 
 pub fn auth_routes() -> Scope {
     web::scope("/auth")
@@ -17,71 +19,58 @@ pub fn auth_routes() -> Scope {
         .route("/logout", web::post().to(logout))
 }
 
-pub async fn start_github_oauth(_session: Session, data: web::Data<AppState>) -> impl Responder {
-    let AppState {
-        github_client_id,
-        ..
-    } = &**data;
+pub async fn start_github_oauth(
+    session: Session,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let github = &data.config.github;
 
     let state = generate_secure_random_string(20);
-    _session.insert("oauth_state", &state).expect("Failed to set state");
+    session.insert("oauth_state", &state).map_err(ApiError::internal)?;
 
-    let github_oauth_url = generate_oauth_url(github_client_id.clone(), state);
-    HttpResponse::Found()
+    let github_oauth_url = generate_oauth_url(&github.client_id, &github.redirect_uri, &state);
+    Ok(HttpResponse::Found()
         .append_header(("Location", github_oauth_url))
-        .finish()
+        .finish())
 }
 
 pub async fn github_oauth_redirect(
     web::Query(params): web::Query<HashMap<String, String>>,
     session: Session,
     data: web::Data<AppState>,
-) -> impl Responder {
-    let AppState {
-        github_client_id,
-        github_client_secret,
-        ..
-    } = &**data;
-
-    let received_state = match params.get("state") {
-        Some(state) => state,
-        None => return HttpResponse::BadRequest().body("Parameter error"),
-    };
+) -> Result<HttpResponse, ApiError> {
+    let github = &data.config.github;
+
+    let received_state = params
+        .get("state")
+        .ok_or_else(|| ApiError::invalid("Missing state parameter"))?;
 
     let stored_state: String = session.get::<String>("oauth_state").unwrap_or(None).unwrap_or_default();
 
     if stored_state != *received_state {
-        return HttpResponse::BadRequest().body("Parameter error");
+        return Err(ApiError::invalid("OAuth state does not match"));
     }
 
-    let code = match params.get("code") {
-        Some(code) => code,
-        None => return HttpResponse::BadRequest().body("Parameter error"),
-    };
+    let code = params
+        .get("code")
+        .ok_or_else(|| ApiError::invalid("Missing code parameter"))?;
 
-    let (github_user_id, github_user_name) =
-    match exchange_code_for_user_id(github_client_id, github_client_secret, code).await {
-        Ok(info) => info,
-        Err(e) => {
-            eprintln!("OAuth error: {e:?}"); // or use log::error! if you prefer
-            return HttpResponse::InternalServerError()
-                .body("Internal server error")
-        }
-    };
+    let started = Instant::now();
+    let exchange = exchange_code_for_user_id(&github.client_id, &github.client_secret, code).await;
+    data.metrics.github_call("oauth", started.elapsed(), exchange.is_ok());
+    let (github_user_id, github_user_name) = exchange.map_err(ApiError::Upstream)?;
 
-    if session.insert("user_id", &github_user_id).is_err() {
-        return HttpResponse::InternalServerError().body("Internal server error");
-    }
+    upsert_user(&data, &github_user_id, &github_user_name).await?;
 
-    if session.insert("user_name", &github_user_name).is_err() {
-        return HttpResponse::InternalServerError().body("Internal server error");
-    }
+    session.insert("user_id", &github_user_id).map_err(ApiError::internal)?;
+    session.insert("user_name", &github_user_name).map_err(ApiError::internal)?;
 
-    HttpResponse::Found()
+    Ok(HttpResponse::Found()
         .append_header(("Location", "/home"))
-        .finish()
+        .finish())
 }
 
+#[tracing::instrument(name = "github.oauth_exchange", skip_all, fields(otel.kind = "client"))]
 pub async fn exchange_code_for_user_id(
     client_id: &str,
     client_secret: &str,
@@ -142,8 +131,7 @@ fn generate_secure_random_string(length: usize) -> String {
         .collect()
 }
 
-pub fn generate_oauth_url(client_id: String, state: String) -> String {
-    let redirect_uri = env::var("REDIRECT_URI").expect("Environment variable error");
+pub fn generate_oauth_url(client_id: &str, redirect_uri: &str, state: &str) -> String {
     format!(
         "https://github.com/login/oauth/authorize?client_id={client_id}&redirect_uri={redirect_uri}&state={state}&scope=read:user%20user:email"
     )
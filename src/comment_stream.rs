@@ -0,0 +1,260 @@
+use crate::comments::{Comment, COMMENT_COLUMNS};
+use crate::moderation::VISIBLE_COMMENT_FILTER;
+use crate::supervisor::{Shutdown, Supervisor};
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use futures_util::stream;
+use sqlx::postgres::PgListener;
+use sqlx::{FromRow, Pool, Postgres};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+/// Postgres channel the `comments` trigger notifies with new event ids.
+const CHANNEL: &str = "comment_events";
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// Client reconnect delay advertised with `retry:`, in milliseconds.
+const RETRY_MS: u32 = 3000;
+/// A client further behind than this is told to reload instead of replaying.
+const MAX_REPLAY: i64 = 500;
+/// Events a slow client may fall behind by before it is disconnected; it
+/// resumes from `Last-Event-ID` when it reconnects.
+const BROADCAST_CAPACITY: usize = 256;
+
+#[derive(FromRow)]
+struct EventRow {
+    id: i64,
+    comment_id: i32,
+    kind: String,
+}
+
+/// A server-sent event, already encoded for the wire.
+struct Event {
+    id: i64,
+    frame: Bytes,
+}
+
+/// Fans guestbook comment changes from Postgres out to SSE clients. Every
+/// instance listens on the same channel, so a comment written through one
+/// reaches readers connected to any of them.
+pub struct CommentStream {
+    db: Pool<Postgres>,
+    sender: broadcast::Sender<Arc<Event>>,
+    shutdown: Shutdown,
+}
+
+impl CommentStream {
+    /// Starts listening for comment events in the background. Open streams
+    /// are closed when shutdown begins so clients reconnect elsewhere.
+    pub fn start(db: Pool<Postgres>, supervisor: &mut Supervisor) -> CommentStream {
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        let listener_db = db.clone();
+        let listener_sender = sender.clone();
+        supervisor.spawn("comment-listener", move || {
+            let db = listener_db.clone();
+            let sender = listener_sender.clone();
+            async move { listen(&db, &sender).await }
+        });
+        CommentStream {
+            db,
+            sender,
+            shutdown: supervisor.shutdown(),
+        }
+    }
+}
+
+async fn listen(
+    db: &Pool<Postgres>,
+    sender: &broadcast::Sender<Arc<Event>>,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(db).await?;
+    listener.listen(CHANNEL).await?;
+    loop {
+        let notification = listener.recv().await?;
+        let Ok(id) = notification.payload().parse::<i64>() else {
+            continue;
+        };
+        for event in render_events(db, "e.id = $1", id, 1).await? {
+            // Nobody listening is fine; the event is kept for replay.
+            let _ = sender.send(Arc::new(event));
+        }
+    }
+}
+
+fn frame(id: Option<i64>, event: &str, data: &str) -> Bytes {
+    let id = id.map(|id| format!("id: {id}\n")).unwrap_or_default();
+    Bytes::from(format!("{id}event: {event}\ndata: {data}\n\n"))
+}
+
+/// Turns guestbook events matching `filter` into SSE frames. Comments are
+/// read as they are now, so one that was created and then hidden by a
+/// moderator is never shown, and an edit that hides a comment is sent as a
+/// deletion.
+async fn render_events(
+    db: &Pool<Postgres>,
+    filter: &str,
+    id: i64,
+    limit: i64,
+) -> Result<Vec<Event>, sqlx::Error> {
+    let events = sqlx::query_as::<_, EventRow>(&format!(
+        "SELECT e.id, e.comment_id, e.kind FROM comment_events e \
+         WHERE {filter} AND e.post_id IS NULL ORDER BY e.id LIMIT $2"
+    ))
+    .bind(id)
+    .bind(limit)
+    .fetch_all(db)
+    .await?;
+    if events.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ids: Vec<i32> = events.iter().map(|event| event.comment_id).collect();
+    let comments: HashMap<i32, Comment> = sqlx::query_as::<_, Comment>(&format!(
+        "SELECT {COMMENT_COLUMNS} FROM comments c \
+         WHERE id = ANY($1) AND deleted_at IS NULL AND {VISIBLE_COMMENT_FILTER}"
+    ))
+    .bind(&ids)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|comment| (comment.id, comment))
+    .collect();
+
+    Ok(events
+        .into_iter()
+        .filter_map(|event| {
+            let frame = match (event.kind.as_str(), comments.get(&event.comment_id)) {
+                ("created" | "edited", Some(comment)) => frame(
+                    Some(event.id),
+                    &event.kind,
+                    &serde_json::to_string(comment).ok()?,
+                ),
+                ("created", None) => return None,
+                _ => frame(
+                    Some(event.id),
+                    "deleted",
+                    &serde_json::json!({ "id": event.comment_id }).to_string(),
+                ),
+            };
+            Some(Event {
+                id: event.id,
+                frame,
+            })
+        })
+        .collect())
+}
+
+async fn latest_event_id(db: &Pool<Postgres>) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM comment_events")
+        .fetch_one(db)
+        .await
+}
+
+/// Feeds one client until it disconnects or the server shuts down: replay
+/// from `last_event_id`, then live events interleaved with heartbeats.
+async fn run_client(
+    db: Pool<Postgres>,
+    mut events: broadcast::Receiver<Arc<Event>>,
+    mut shutdown: Shutdown,
+    last_event_id: Option<i64>,
+    client: mpsc::Sender<Bytes>,
+) -> Result<(), sqlx::Error> {
+    // Subscribed before replaying, so anything committed in between arrives
+    // on `events` and is skipped if the replay already covered it.
+    let mut last_sent = 0;
+    let mut too_far_behind = false;
+    let missed = match last_event_id {
+        Some(last_event_id) => {
+            last_sent = last_event_id;
+            // Events older than a day are pruned, so a gap means some are gone.
+            let oldest: Option<i64> = sqlx::query_scalar("SELECT MIN(id) FROM comment_events")
+                .fetch_one(&db)
+                .await?;
+            too_far_behind = oldest.is_some_and(|oldest| oldest > last_event_id + 1);
+            render_events(&db, "e.id > $1", last_event_id, MAX_REPLAY + 1).await?
+        }
+        None => Vec::new(),
+    };
+    // A fresh client gets the current position so a reconnect can resume
+    // even if no event has arrived in between.
+    let opening = match last_event_id {
+        Some(_) => format!("retry: {RETRY_MS}\n\n"),
+        None => format!("retry: {RETRY_MS}\nid: {}\n\n", latest_event_id(&db).await?),
+    };
+    if client.send(Bytes::from(opening)).await.is_err() {
+        return Ok(());
+    }
+
+    if too_far_behind || missed.len() as i64 > MAX_REPLAY {
+        last_sent = latest_event_id(&db).await?;
+        if client.send(frame(Some(last_sent), "reset", "{}")).await.is_err() {
+            return Ok(());
+        }
+    } else {
+        for event in missed {
+            last_sent = last_sent.max(event.id);
+            if client.send(event.frame.clone()).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    let mut heartbeat = actix_web::rt::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await;
+    loop {
+        let frame = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if event.id <= last_sent => continue,
+                Ok(event) => {
+                    last_sent = event.id;
+                    event.frame.clone()
+                }
+                // Too far behind: drop the connection and let the client resume.
+                Err(_) => return Ok(()),
+            },
+            _ = heartbeat.tick() => frame(None, "heartbeat", "{}"),
+            _ = shutdown.wait() => return Ok(()),
+        };
+        if client.send(frame).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Live guestbook updates as Server-Sent Events: `created`, `edited` and
+/// `deleted` carry the comment (or its id), `heartbeat` keeps proxies from
+/// closing idle connections and `reset` asks the client to refetch
+/// `/api/comments`. Reconnecting clients resume from `Last-Event-ID`.
+#[get("/comments/stream")]
+pub async fn stream_comments(req: HttpRequest, stream: web::Data<CommentStream>) -> HttpResponse {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+
+    let (client, frames) = mpsc::channel(16);
+    let events = stream.sender.subscribe();
+    let db = stream.db.clone();
+    let shutdown = stream.shutdown.clone();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = run_client(db, events, shutdown, last_event_id, client).await {
+            sentry::capture_error(&e);
+        }
+    });
+
+    let body = stream::unfold(frames, |mut frames| async move {
+        frames
+            .recv()
+            .await
+            .map(|frame| (Ok::<_, Infallible>(frame), frames))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body)
+}
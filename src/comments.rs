@@ -0,0 +1,313 @@
+use crate::error::ApiError;
+use crate::moderation::{self, CommentStatus, VISIBLE_COMMENT_FILTER};
+use crate::pagination::{Cursor, Page, PageQuery};
+use crate::slugs;
+use crate::spam::{self, Verdict};
+use crate::state::AppState;
+use crate::users::{AuthenticatedUser, Permission};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+
+const MAX_CHARS: usize = 255;
+
+/// Replies may nest this many levels below a top-level comment.
+const MAX_DEPTH: i32 = 4;
+
+/// Deleted comments come back as tombstones: the row keeps its place in a
+/// thread (and its original text for moderators) but the author and body are blanked.
+pub(crate) const COMMENT_COLUMNS: &str = "id, userid, \
+     CASE WHEN deleted_at IS NULL THEN name ELSE '' END AS name, \
+     CASE WHEN deleted_at IS NULL THEN comment ELSE '' END AS comment, \
+     timestamp, edited_at, deleted_at IS NOT NULL AS deleted, status, post_id, parent_id";
+
+#[derive(Serialize, FromRow)]
+pub(crate) struct Comment {
+    pub(crate) id: i32,
+    #[serde(skip_serializing)]
+    userid: String,
+    name: String,
+    comment: String,
+    timestamp: DateTime<Utc>,
+    edited_at: Option<DateTime<Utc>>,
+    deleted: bool,
+    status: CommentStatus,
+    post_id: Option<i32>,
+    parent_id: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct CommentNode {
+    #[serde(flatten)]
+    comment: Comment,
+    replies: Vec<CommentNode>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateComment {
+    pub comment: String,
+    pub parent_id: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct EditComment {
+    pub comment: String,
+}
+
+/// Nests a flat, oldest-first list of comments under their parents.
+fn build_tree(comments: Vec<Comment>) -> Vec<CommentNode> {
+    let mut children: HashMap<Option<i32>, Vec<Comment>> = HashMap::new();
+    for comment in comments {
+        children.entry(comment.parent_id).or_default().push(comment);
+    }
+
+    fn attach(
+        parent: Option<i32>,
+        children: &mut HashMap<Option<i32>, Vec<Comment>>,
+    ) -> Vec<CommentNode> {
+        children
+            .remove(&parent)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|comment| CommentNode {
+                replies: attach(Some(comment.id), children),
+                comment,
+            })
+            // A tombstone is only worth showing while it still holds replies.
+            .filter(|node| !node.comment.deleted || !node.replies.is_empty())
+            .collect()
+    }
+
+    attach(None, &mut children)
+}
+
+/// Inserts a comment on a post (or the guestbook when `post_id` is `None`).
+/// Replies must stay on the same post as their parent and within `MAX_DEPTH`.
+async fn insert_comment(
+    user: &AuthenticatedUser,
+    post_id: Option<i32>,
+    body: &CreateComment,
+    data: &AppState,
+) -> Result<HttpResponse, ApiError> {
+    if user.id.len() > MAX_CHARS || user.name.len() > MAX_CHARS || body.comment.len() > MAX_CHARS {
+        return Err(ApiError::invalid("Input exceeds maximum allowed characters"));
+    }
+    let sanitized_comment = ammonia::clean(&body.comment);
+
+    let depth = match body.parent_id {
+        None => 0,
+        Some(parent_id) => match sqlx::query_as::<_, (Option<i32>, i32, bool)>(
+            &format!(
+                "SELECT post_id, depth, deleted_at IS NOT NULL FROM comments c \
+                 WHERE id = $1 AND {VISIBLE_COMMENT_FILTER}"
+            ),
+        )
+        .bind(parent_id)
+        .fetch_optional(&data.db)
+        .await?
+        {
+            Some((parent_post, depth, false)) if parent_post == post_id => {
+                if depth >= MAX_DEPTH {
+                    return Err(ApiError::invalid("Maximum reply depth reached"));
+                }
+                depth + 1
+            }
+            _ => return Err(ApiError::invalid("Parent comment not found")),
+        },
+    };
+
+    let (status, verdict) = moderation::screen_comment(user, None, &sanitized_comment, data).await?;
+
+    let mut tx = data.db.begin().await?;
+    let comment = sqlx::query_as::<_, Comment>(&format!(
+        "INSERT INTO comments (userid, name, comment, post_id, parent_id, depth, status) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {COMMENT_COLUMNS}"
+    ))
+    .bind(&user.id)
+    .bind(&user.name)
+    .bind(&sanitized_comment)
+    .bind(post_id)
+    .bind(body.parent_id)
+    .bind(depth)
+    .bind(status)
+    .fetch_one(&mut *tx)
+    .await?;
+    if let Some(verdict) = &verdict {
+        spam::record_verdict(&mut *tx, comment.id, verdict).await?;
+    }
+    tx.commit().await?;
+    data.metrics.comment_created(comment.status);
+
+    screened_response(comment, verdict.as_ref())
+}
+
+/// Rejected comments are kept for auditing but the author only learns that
+/// the filter turned them down.
+fn screened_response(comment: Comment, verdict: Option<&Verdict>) -> Result<HttpResponse, ApiError> {
+    match comment.status {
+        CommentStatus::Rejected if verdict.is_some() => Err(ApiError::Unprocessable(
+            "Comment was rejected by the spam filter".into(),
+        )),
+        _ => Ok(HttpResponse::Ok().json(comment)),
+    }
+}
+
+#[get("/comments")]
+pub async fn fetch_comments(
+    req: HttpRequest,
+    query: web::Query<PageQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let cursor = query.cursor::<DateTime<Utc>, i32>()?;
+    let limit = query.limit();
+    let (keyset, order) = Cursor::sql(cursor.as_ref(), "c.timestamp", "c.id", 2);
+
+    let sql = format!(
+        "SELECT {COMMENT_COLUMNS} FROM comments c \
+         WHERE post_id IS NULL AND deleted_at IS NULL AND {VISIBLE_COMMENT_FILTER} AND {keyset} \
+         ORDER BY {order} LIMIT $1"
+    );
+    let mut comments = sqlx::query_as::<_, Comment>(&sql).bind(limit + 1);
+    if let Some(cursor) = &cursor {
+        comments = comments.bind(cursor.key).bind(cursor.id);
+    }
+
+    let comments = comments.fetch_all(&state.db).await?;
+    Ok(HttpResponse::Ok().json(Page::new(
+        &req,
+        limit,
+        cursor.as_ref(),
+        comments,
+        |comment| (comment.timestamp, comment.id),
+    )))
+}
+
+#[post("/comments")]
+pub async fn create_comment(
+    user: AuthenticatedUser,
+    comment_body: web::Json<CreateComment>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::Comment)?;
+
+    insert_comment(&user, None, &comment_body, &data).await
+}
+
+/// Comment thread for a post, nested by `parent_id`.
+#[get("/posts/{key}/comments")]
+pub async fn fetch_post_comments(
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let post_id = slugs::public_post(&data.db, &path.into_inner()).await?.id;
+
+    let comments = sqlx::query_as::<_, Comment>(&format!(
+        "SELECT {COMMENT_COLUMNS} FROM comments c \
+         WHERE post_id = $1 AND {VISIBLE_COMMENT_FILTER} ORDER BY timestamp, id"
+    ))
+    .bind(post_id)
+    .fetch_all(&data.db)
+    .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": comments.iter().filter(|comment| !comment.deleted).count(),
+        "comments": build_tree(comments)
+    })))
+}
+
+#[post("/posts/{key}/comments")]
+pub async fn create_post_comment(
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    comment_body: web::Json<CreateComment>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::Comment)?;
+
+    let post_id = slugs::public_post(&data.db, &path.into_inner()).await?.id;
+
+    insert_comment(&user, Some(post_id), &comment_body, &data).await
+}
+
+/// Loads a comment for its author, failing with 404 when it is missing or
+/// already deleted and 403 when it belongs to someone else.
+async fn own_comment(
+    user: &AuthenticatedUser,
+    comment_id: i32,
+    data: &AppState,
+) -> Result<Comment, ApiError> {
+    match sqlx::query_as::<_, Comment>(&format!(
+        "SELECT {COMMENT_COLUMNS} FROM comments WHERE id = $1 AND deleted_at IS NULL"
+    ))
+    .bind(comment_id)
+    .fetch_optional(&data.db)
+    .await?
+    {
+        Some(comment) if comment.userid == user.id => Ok(comment),
+        Some(_) => Err(ApiError::forbidden("You can only change your own comments")),
+        None => Err(ApiError::not_found("Comment not found")),
+    }
+}
+
+#[put("/comments/{id}")]
+pub async fn edit_comment(
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    body: web::Json<EditComment>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::Comment)?;
+    if body.comment.len() > MAX_CHARS {
+        return Err(ApiError::invalid("Input exceeds maximum allowed characters"));
+    }
+
+    let comment = own_comment(&user, path.into_inner(), &data).await?;
+    if comment.status == CommentStatus::Rejected {
+        return Err(ApiError::forbidden("Rejected comments cannot be edited"));
+    }
+    if Utc::now() - comment.timestamp > data.config.comments.edit_window {
+        return Err(ApiError::forbidden("The edit window for this comment has closed"));
+    }
+
+    // Edits go back through moderation and the spam filter so an approved
+    // comment cannot be rewritten into something that would have been held.
+    let sanitized_comment = ammonia::clean(&body.comment);
+    let (screened, verdict) =
+        moderation::screen_comment(&user, Some(comment.id), &sanitized_comment, &data).await?;
+    let status = comment.status.after_edit(screened);
+
+    let mut tx = data.db.begin().await?;
+    let comment = sqlx::query_as::<_, Comment>(&format!(
+        "UPDATE comments SET comment = $1, edited_at = now(), status = $2 \
+         WHERE id = $3 RETURNING {COMMENT_COLUMNS}"
+    ))
+    .bind(&sanitized_comment)
+    .bind(status)
+    .bind(comment.id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if let Some(verdict) = &verdict {
+        spam::record_verdict(&mut *tx, comment.id, verdict).await?;
+    }
+    tx.commit().await?;
+
+    screened_response(comment, verdict.as_ref())
+}
+
+/// Authors may retract a comment at any time. The row is kept as a tombstone
+/// so replies stay threaded and moderators can still see what was said.
+#[delete("/comments/{id}")]
+pub async fn retract_comment(
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let comment = own_comment(&user, path.into_inner(), &data).await?;
+
+    sqlx::query("UPDATE comments SET deleted_at = now() WHERE id = $1")
+        .bind(comment.id)
+        .execute(&data.db)
+        .await?;
+    Ok(HttpResponse::Ok().json("Comment deleted successfully"))
+}
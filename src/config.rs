@@ -0,0 +1,518 @@
+use crate::moderation::ModerationMode;
+use crate::rate_limit::ProxyNet;
+use crate::spam;
+use serde::Deserialize;
+use std::env;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Read when `CONFIG_FILE` isn't set, if it exists.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Settings as written in the TOML file. Everything is optional here so
+/// environment variables can fill in or override any of it before
+/// [`FileConfig::validate`] checks the result.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    port: Option<u16>,
+    environment: Option<String>,
+    site_url: Option<String>,
+    database_url: Option<String>,
+    secret_key: Option<String>,
+    github: GithubSection,
+    sentry: SentrySection,
+    comments: CommentSection,
+    spam: SpamSection,
+    rate_limit: RateLimitSection,
+    analytics: AnalyticsSection,
+    metrics: MetricsSection,
+    logging: LoggingSection,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GithubSection {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect_uri: Option<String>,
+    owner: Option<String>,
+    repo: Option<String>,
+    admin_ids: Option<Vec<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SentrySection {
+    dsn: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CommentSection {
+    edit_window_minutes: Option<i64>,
+    moderation: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SpamSection {
+    blocklist_file: Option<String>,
+    queue_score: Option<f32>,
+    reject_score: Option<f32>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RateLimitSection {
+    store: Option<String>,
+    trusted_proxies: Option<Vec<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AnalyticsSection {
+    geoip_database: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MetricsSection {
+    token: Option<String>,
+    port: Option<u16>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    format: Option<String>,
+    otlp_endpoint: Option<String>,
+}
+
+/// Everything the server needs to start, loaded once in `main` and shared
+/// through `AppState`.
+pub struct Config {
+    pub port: u16,
+    /// Anything other than "production" is served a robots.txt that
+    /// disallows crawling.
+    pub environment: String,
+    /// Public origin used for absolute links, without a trailing slash.
+    pub site_url: String,
+    pub database_url: String,
+    /// Signs session cookies and preview links.
+    pub secret_key: Vec<u8>,
+    /// Error reporting is off when unset.
+    pub sentry_dsn: Option<String>,
+    pub github: GithubConfig,
+    pub comments: CommentConfig,
+    pub spam: SpamConfig,
+    pub rate_limit: RateLimitConfig,
+    /// Optional GeoLite2/GeoIP2 country database for analytics.
+    pub geoip_database: Option<String>,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+}
+
+pub struct GithubConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    /// The repository whose stars are shown on the home page.
+    pub owner: String,
+    pub repo: String,
+    /// GitHub ids granted the admin role on login.
+    pub admin_ids: Vec<String>,
+}
+
+pub struct CommentConfig {
+    /// How long after posting a user may still edit their comment.
+    pub edit_window: chrono::Duration,
+    pub moderation: ModerationMode,
+}
+
+pub struct SpamConfig {
+    pub blocklist_file: Option<String>,
+    pub queue_score: f32,
+    pub reject_score: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitStore {
+    Memory,
+    /// Shares buckets between instances.
+    Postgres,
+}
+
+pub struct RateLimitConfig {
+    pub store: RateLimitStore,
+    /// Proxies whose Fly-Client-IP and X-Forwarded-For headers are trusted.
+    pub trusted_proxies: Vec<ProxyNet>,
+}
+
+/// `/metrics` is only served when at least one of these is set.
+pub struct MetricsConfig {
+    /// Bearer token scrapers must send.
+    pub token: Option<String>,
+    /// Serve `/metrics` on its own port instead of the public one.
+    pub port: Option<u16>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines for a terminal.
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Base URL of an OTLP/HTTP collector (e.g. `http://localhost:4318`)
+    /// that receives traces. Export is off when unset.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Config {
+    /// Reads `CONFIG_FILE` (or `config.toml` if present), applies environment
+    /// overrides and validates the result. Every problem found is reported,
+    /// one per line.
+    pub fn load() -> Result<Config, String> {
+        let mut file = FileConfig::find()?;
+        let mut errors = Vec::new();
+        file.apply_env(&mut errors);
+        let config = file.validate(&mut errors);
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+/// Only the database URL, from the same sources as [`Config::load`], for
+/// commands like `migrate` that don't need the rest of the configuration.
+pub fn database_url() -> Result<String, String> {
+    let mut file = FileConfig::find()?;
+    override_string(&mut file.database_url, "DATABASE_URL");
+    let mut errors = Vec::new();
+    let database_url = required(file.database_url, "database_url (DATABASE_URL)", &mut errors);
+    if errors.is_empty() {
+        Ok(database_url)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// The value of `name`, treating an empty variable as unset.
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn override_string(slot: &mut Option<String>, name: &str) {
+    if let Some(value) = var(name) {
+        *slot = Some(value);
+    }
+}
+
+fn override_parsed<T: FromStr>(slot: &mut Option<T>, name: &str, errors: &mut Vec<String>) {
+    if let Some(value) = var(name) {
+        match value.trim().parse() {
+            Ok(value) => *slot = Some(value),
+            Err(_) => errors.push(format!("{name}: {value:?} is not a valid number")),
+        }
+    }
+}
+
+/// Comma-separated lists, as `ADMIN_GITHUB_IDS` and `TRUSTED_PROXIES` are
+/// written.
+fn override_list(slot: &mut Option<Vec<String>>, name: &str) {
+    if let Some(value) = var(name) {
+        *slot = Some(value.split(',').map(str::to_string).collect());
+    }
+}
+
+fn required(value: Option<String>, name: &str, errors: &mut Vec<String>) -> String {
+    match value.filter(|value| !value.trim().is_empty()) {
+        Some(value) => value,
+        None => {
+            errors.push(format!("{name} must be set"));
+            String::new()
+        }
+    }
+}
+
+fn is_http_url(value: &str) -> bool {
+    url::Url::parse(value).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+impl FileConfig {
+    /// Reads `CONFIG_FILE`, or `config.toml` if present.
+    fn find() -> Result<FileConfig, String> {
+        match env::var("CONFIG_FILE") {
+            Ok(path) if !path.is_empty() => FileConfig::read(&path),
+            _ if Path::new(DEFAULT_CONFIG_FILE).exists() => FileConfig::read(DEFAULT_CONFIG_FILE),
+            _ => Ok(FileConfig::default()),
+        }
+    }
+
+    fn read(path: &str) -> Result<FileConfig, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        toml::from_str(&contents).map_err(|e| format!("{path}: {e}"))
+    }
+
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        override_parsed(&mut self.port, "PORT", errors);
+        override_string(&mut self.environment, "ENVIRONMENT");
+        override_string(&mut self.site_url, "SITE_URL");
+        override_string(&mut self.database_url, "DATABASE_URL");
+        override_string(&mut self.secret_key, "SECRET_KEY");
+
+        override_string(&mut self.github.client_id, "GITHUB_CLIENT_ID");
+        override_string(&mut self.github.client_secret, "GITHUB_CLIENT_SECRET");
+        override_string(&mut self.github.redirect_uri, "REDIRECT_URI");
+        override_string(&mut self.github.owner, "GITHUB_OWNER");
+        override_string(&mut self.github.repo, "GITHUB_REPO");
+        override_list(&mut self.github.admin_ids, "ADMIN_GITHUB_IDS");
+
+        override_string(&mut self.sentry.dsn, "SENTRY_DSN");
+
+        override_parsed(
+            &mut self.comments.edit_window_minutes,
+            "COMMENT_EDIT_WINDOW_MINUTES",
+            errors,
+        );
+        override_string(&mut self.comments.moderation, "COMMENT_MODERATION");
+
+        override_string(&mut self.spam.blocklist_file, "SPAM_BLOCKLIST_FILE");
+        override_parsed(&mut self.spam.queue_score, "SPAM_QUEUE_SCORE", errors);
+        override_parsed(&mut self.spam.reject_score, "SPAM_REJECT_SCORE", errors);
+
+        override_string(&mut self.rate_limit.store, "RATE_LIMIT_STORE");
+        override_list(&mut self.rate_limit.trusted_proxies, "TRUSTED_PROXIES");
+
+        override_string(&mut self.analytics.geoip_database, "GEOIP_DATABASE");
+
+        override_string(&mut self.metrics.token, "METRICS_TOKEN");
+        override_parsed(&mut self.metrics.port, "METRICS_PORT", errors);
+
+        override_string(&mut self.logging.format, "LOG_FORMAT");
+        override_string(&mut self.logging.otlp_endpoint, "OTEL_EXPORTER_OTLP_ENDPOINT");
+    }
+
+    fn validate(self, errors: &mut Vec<String>) -> Config {
+        let database_url = required(self.database_url, "database_url (DATABASE_URL)", errors);
+
+        let secret_key = match self.secret_key.filter(|key| !key.is_empty()) {
+            None => {
+                errors.push("secret_key (SECRET_KEY) must be set".to_string());
+                Vec::new()
+            }
+            Some(key) => match hex::decode(key.trim()) {
+                // The private cookie session refuses shorter keys.
+                Ok(key) if key.len() >= 32 => key,
+                Ok(_) => {
+                    errors.push(
+                        "secret_key (SECRET_KEY) must be at least 32 bytes (64 hex characters)"
+                            .to_string(),
+                    );
+                    Vec::new()
+                }
+                Err(_) => {
+                    errors.push("secret_key (SECRET_KEY) must be hex-encoded".to_string());
+                    Vec::new()
+                }
+            },
+        };
+
+        let site_url = self
+            .site_url
+            .unwrap_or_else(|| "https://rayspace.dev".to_string())
+            .trim_end_matches('/')
+            .to_string();
+        if !is_http_url(&site_url) {
+            errors.push(format!(
+                "site_url (SITE_URL): {site_url:?} is not an http(s) URL"
+            ));
+        }
+
+        let redirect_uri = required(
+            self.github.redirect_uri,
+            "github.redirect_uri (REDIRECT_URI)",
+            errors,
+        );
+        if !redirect_uri.is_empty() && !is_http_url(&redirect_uri) {
+            errors.push(format!(
+                "github.redirect_uri (REDIRECT_URI): {redirect_uri:?} is not an http(s) URL"
+            ));
+        }
+        let github = GithubConfig {
+            client_id: required(
+                self.github.client_id,
+                "github.client_id (GITHUB_CLIENT_ID)",
+                errors,
+            ),
+            client_secret: required(
+                self.github.client_secret,
+                "github.client_secret (GITHUB_CLIENT_SECRET)",
+                errors,
+            ),
+            redirect_uri,
+            owner: self.github.owner.unwrap_or_else(|| "rx0a".to_string()),
+            repo: self
+                .github
+                .repo
+                .unwrap_or_else(|| "rayspace.dev".to_string()),
+            admin_ids: self
+                .github
+                .admin_ids
+                .unwrap_or_default()
+                .iter()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty())
+                .collect(),
+        };
+
+        let edit_window_minutes = self.comments.edit_window_minutes.unwrap_or(15);
+        if edit_window_minutes < 0 {
+            errors.push(
+                "comments.edit_window_minutes (COMMENT_EDIT_WINDOW_MINUTES) must not be negative"
+                    .to_string(),
+            );
+        }
+        let moderation = match self.comments.moderation.as_deref() {
+            None => ModerationMode::AutoApprove,
+            Some(mode) => ModerationMode::parse(mode.trim()).unwrap_or_else(|| {
+                errors.push(format!(
+                    "comments.moderation (COMMENT_MODERATION): {mode:?} must be one of auto, first_time or all"
+                ));
+                ModerationMode::AutoApprove
+            }),
+        };
+
+        let queue_score = self.spam.queue_score.unwrap_or(spam::DEFAULT_QUEUE_SCORE);
+        let reject_score = self.spam.reject_score.unwrap_or(spam::DEFAULT_REJECT_SCORE);
+        let valid_score = |score: f32| score > 0.0 && score.is_finite();
+        if !valid_score(queue_score) || !valid_score(reject_score) {
+            errors.push("spam.queue_score and spam.reject_score must be positive".to_string());
+        } else if queue_score > reject_score {
+            errors.push(format!(
+                "spam.queue_score ({queue_score}) must not be above spam.reject_score ({reject_score})"
+            ));
+        }
+
+        let store = match self.rate_limit.store.as_deref().map(str::trim) {
+            None | Some("memory") => RateLimitStore::Memory,
+            Some("postgres") => RateLimitStore::Postgres,
+            Some(store) => {
+                errors.push(format!(
+                    "rate_limit.store (RATE_LIMIT_STORE): {store:?} must be memory or postgres"
+                ));
+                RateLimitStore::Memory
+            }
+        };
+        let trusted_proxies = self
+            .rate_limit
+            .trusted_proxies
+            .unwrap_or_default()
+            .iter()
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let net = ProxyNet::parse(entry);
+                if net.is_none() {
+                    errors.push(format!(
+                        "rate_limit.trusted_proxies (TRUSTED_PROXIES): {entry:?} is not an address or CIDR block"
+                    ));
+                }
+                net
+            })
+            .collect();
+
+        let format = match self.logging.format.as_deref().map(str::trim) {
+            None | Some("text") => LogFormat::Text,
+            Some("json") => LogFormat::Json,
+            Some(format) => {
+                errors.push(format!(
+                    "logging.format (LOG_FORMAT): {format:?} must be text or json"
+                ));
+                LogFormat::Text
+            }
+        };
+        let otlp_endpoint = self
+            .logging
+            .otlp_endpoint
+            .map(|endpoint| endpoint.trim().trim_end_matches('/').to_string())
+            .filter(|endpoint| !endpoint.is_empty());
+        if let Some(endpoint) = &otlp_endpoint {
+            if !is_http_url(endpoint) {
+                errors.push(format!(
+                    "logging.otlp_endpoint (OTEL_EXPORTER_OTLP_ENDPOINT): {endpoint:?} is not an http(s) URL"
+                ));
+            }
+        }
+
+        let port = self.port.unwrap_or(8080);
+        if self.metrics.port == Some(port) {
+            errors.push(format!(
+                "metrics.port (METRICS_PORT) must differ from port ({port})"
+            ));
+        }
+
+        Config {
+            port,
+            environment: self
+                .environment
+                .unwrap_or_else(|| "development".to_string()),
+            site_url,
+            database_url,
+            secret_key,
+            sentry_dsn: self.sentry.dsn.filter(|dsn| !dsn.is_empty()),
+            github,
+            comments: CommentConfig {
+                edit_window: chrono::Duration::minutes(edit_window_minutes),
+                moderation,
+            },
+            spam: SpamConfig {
+                blocklist_file: self.spam.blocklist_file.filter(|path| !path.is_empty()),
+                queue_score,
+                reject_score,
+            },
+            rate_limit: RateLimitConfig {
+                store,
+                trusted_proxies,
+            },
+            geoip_database: self
+                .analytics
+                .geoip_database
+                .filter(|path| !path.is_empty()),
+            metrics: MetricsConfig {
+                token: self.metrics.token.filter(|token| !token.is_empty()),
+                port: self.metrics.port,
+            },
+            logging: LoggingConfig {
+                format,
+                otlp_endpoint,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// A valid configuration for handler tests, without reading the
+    /// environment.
+    pub fn for_tests(database_url: &str) -> Config {
+        let mut file = FileConfig {
+            database_url: Some(database_url.to_string()),
+            secret_key: Some("ab".repeat(64)),
+            ..FileConfig::default()
+        };
+        file.github.client_id = Some("client".to_string());
+        file.github.client_secret = Some("secret".to_string());
+        file.github.redirect_uri = Some("http://localhost/auth/github_oauth_redirect".to_string());
+        let mut errors = Vec::new();
+        let config = file.validate(&mut errors);
+        assert!(errors.is_empty(), "{errors:?}");
+        config
+    }
+}
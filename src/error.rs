@@ -0,0 +1,174 @@
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Everything a handler can fail with. Client errors carry a message that is
+/// safe to show; server errors keep their cause for Sentry and show a
+/// generic message instead.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request is malformed or fails validation.
+    Invalid(Cow<'static, str>),
+    /// No user is logged in.
+    Unauthorized,
+    /// The user lacks a permission or doesn't own the resource.
+    Forbidden(Cow<'static, str>),
+    NotFound(Cow<'static, str>),
+    Conflict(Cow<'static, str>),
+    /// Well-formed but refused, e.g. by the spam filter.
+    Unprocessable(Cow<'static, str>),
+    /// Seconds until the client may retry.
+    RateLimited(u64),
+    Database(sqlx::Error),
+    /// A call to another service (GitHub) failed.
+    Upstream(Box<dyn std::error::Error>),
+    Internal(Box<dyn std::error::Error>),
+}
+
+/// The JSON body of every error response.
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: &'a str,
+    request_id: Option<&'a str>,
+}
+
+impl ApiError {
+    pub fn invalid(message: impl Into<Cow<'static, str>>) -> ApiError {
+        ApiError::Invalid(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<Cow<'static, str>>) -> ApiError {
+        ApiError::Forbidden(message.into())
+    }
+
+    pub fn not_found(message: impl Into<Cow<'static, str>>) -> ApiError {
+        ApiError::NotFound(message.into())
+    }
+
+    pub fn conflict(message: impl Into<Cow<'static, str>>) -> ApiError {
+        ApiError::Conflict(message.into())
+    }
+
+    /// For `map_err` on writes that can hit a unique constraint: a violation
+    /// becomes a 409 with `message`, anything else a database error.
+    pub fn on_unique(message: &'static str) -> impl Fn(sqlx::Error) -> ApiError {
+        move |e| match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => ApiError::conflict(message),
+            _ => e.into(),
+        }
+    }
+
+    pub fn internal(error: impl Into<Box<dyn std::error::Error>>) -> ApiError {
+        ApiError::Internal(error.into())
+    }
+
+    /// Stable machine-readable identifier for clients to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Invalid(_) => "invalid_request",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Unprocessable(_) => "unprocessable",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::Database(_) => "database_error",
+            ApiError::Upstream(_) => "upstream_error",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::Invalid(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Unprocessable(message) => message,
+            ApiError::Unauthorized => "User must be logged in",
+            ApiError::RateLimited(_) => "Too many requests, please try again later",
+            ApiError::Database(_) | ApiError::Internal(_) => "An error occurred",
+            ApiError::Upstream(_) => "An upstream service failed",
+        }
+    }
+
+    pub fn render(&self, request_id: Option<&str>) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::RateLimited(retry_after) = self {
+            response.insert_header((RETRY_AFTER, *retry_after));
+        }
+        response.json(ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            request_id,
+        })
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Database(e) => write!(f, "database error: {e}"),
+            ApiError::Upstream(e) => write!(f, "upstream error: {e}"),
+            ApiError::Internal(e) => write!(f, "internal error: {e}"),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Database(e) => Some(e),
+            ApiError::Upstream(e) | ApiError::Internal(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Invalid(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// The body without a request id; [`crate::telemetry::request_id`] fills
+    /// it in.
+    fn error_response(&self) -> HttpResponse {
+        self.render(None)
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> ApiError {
+        match e {
+            sqlx::Error::RowNotFound => ApiError::not_found("Not found"),
+            e => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> ApiError {
+        ApiError::Upstream(Box::new(e))
+    }
+}
+
+/// Error handler for the JSON, query and path extractors, so malformed input
+/// gets the same envelope as validation failures in handlers.
+pub fn extractor_error(e: impl fmt::Display, _: &HttpRequest) -> Error {
+    ApiError::invalid(e.to_string()).into()
+}
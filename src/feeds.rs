@@ -0,0 +1,306 @@
+use crate::error::ApiError;
+use crate::markdown::escape_html;
+use crate::services::PUBLISHED_FILTER;
+use crate::state::AppState;
+use actix_web::http::header::{
+    EntityTag, ETag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified, CACHE_CONTROL,
+    IF_NONE_MATCH,
+};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SITE_TITLE: &str = "rayspace.dev";
+const SITE_DESCRIPTION: &str = "Posts from rayspace.dev";
+const FEED_LENGTH: i64 = 20;
+const SUMMARY_CHARS: usize = 280;
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentMode {
+    #[default]
+    Full,
+    Summary,
+}
+
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    #[serde(default)]
+    pub mode: ContentMode,
+}
+
+#[derive(FromRow)]
+struct FeedRow {
+    slug: String,
+    title: String,
+    published_date: NaiveDate,
+    rendered: String,
+    plain_text: String,
+    updated_at: DateTime<Utc>,
+}
+
+struct FeedEntry {
+    url: String,
+    title: String,
+    published: DateTime<Utc>,
+    updated: DateTime<Utc>,
+    content: String,
+    summary: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn fetch_entries(state: &AppState) -> Result<Vec<FeedEntry>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, FeedRow>(&format!(
+        "SELECT p.slug, p.title, p.published_date, r.rendered, r.plain_text, r.created_at AS updated_at \
+         FROM posts p \
+         JOIN LATERAL (SELECT rendered, plain_text, created_at FROM post_revisions \
+                       WHERE post_id = p.id ORDER BY id DESC LIMIT 1) r ON true \
+         WHERE {PUBLISHED_FILTER} \
+         ORDER BY p.published_date DESC, p.id DESC LIMIT $1"
+    ))
+    .bind(FEED_LENGTH)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let published = row.published_date.and_time(chrono::NaiveTime::MIN).and_utc();
+            FeedEntry {
+                url: format!("{}/blog/{}", state.config.site_url, row.slug),
+                title: row.title,
+                published,
+                // A post edited before its publish date still reports the publish date.
+                updated: row.updated_at.max(published),
+                content: absolutize(&row.rendered, &state.config.site_url),
+                summary: summarize(&row.plain_text),
+            }
+        })
+        .collect())
+}
+
+/// Rewrites root-relative links so feed readers resolve them against the site.
+fn absolutize(html: &str, site_url: &str) -> String {
+    html.replace("href=\"/", &format!("href=\"{site_url}/"))
+        .replace("src=\"/", &format!("src=\"{site_url}/"))
+}
+
+/// Excerpt of a post's plain text, cut at a word boundary.
+fn summarize(text: &str) -> String {
+    let text = text.trim();
+    if text.chars().count() <= SUMMARY_CHARS {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(SUMMARY_CHARS).collect();
+    match cut.rfind(' ') {
+        Some(index) => format!("{}…", &cut[..index]),
+        None => format!("{cut}…"),
+    }
+}
+
+/// The HTML placed in an entry: the rendered post, or the plain-text summary
+/// escaped so it survives being interpreted as HTML.
+fn entry_body(entry: &FeedEntry, mode: ContentMode) -> String {
+    match mode {
+        ContentMode::Full => entry.content.clone(),
+        ContentMode::Summary => escape_html(&entry.summary),
+    }
+}
+
+/// When the newest entry last changed. An empty feed gets a fixed date rather
+/// than the current time, so its body and ETag stay stable between polls.
+fn last_updated(entries: &[FeedEntry]) -> DateTime<Utc> {
+    entries
+        .iter()
+        .map(|e| e.updated)
+        .max()
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+fn render_rss(site_url: &str, entries: &[FeedEntry], mode: ContentMode) -> String {
+    let build_date = last_updated(entries);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\"><channel>\
+         <title>{}</title><link>{site_url}</link><description>{}</description>\
+         <atom:link href=\"{site_url}/feed.xml\" rel=\"self\" type=\"application/rss+xml\"/>\
+         <lastBuildDate>{}</lastBuildDate>",
+        escape_html(SITE_TITLE),
+        escape_html(SITE_DESCRIPTION),
+        build_date.to_rfc2822()
+    );
+    for entry in entries {
+        xml.push_str(&format!(
+            "<item><title>{}</title><link>{}</link><guid isPermaLink=\"true\">{}</guid>\
+             <pubDate>{}</pubDate><description>{}</description></item>",
+            escape_html(&entry.title),
+            entry.url,
+            entry.url,
+            entry.published.to_rfc2822(),
+            escape_html(&entry_body(entry, mode))
+        ));
+    }
+    xml.push_str("</channel></rss>");
+    xml
+}
+
+fn render_atom(site_url: &str, entries: &[FeedEntry], mode: ContentMode) -> String {
+    let updated = last_updated(entries);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\"><title>{}</title><subtitle>{}</subtitle>\
+         <id>{site_url}/</id><link href=\"{site_url}/\" rel=\"alternate\"/>\
+         <link href=\"{site_url}/atom.xml\" rel=\"self\"/><updated>{}</updated>\
+         <author><name>{}</name></author>",
+        escape_html(SITE_TITLE),
+        escape_html(SITE_DESCRIPTION),
+        updated.to_rfc3339(),
+        escape_html(SITE_TITLE)
+    );
+    let element = match mode {
+        ContentMode::Full => "content",
+        ContentMode::Summary => "summary",
+    };
+    for entry in entries {
+        xml.push_str(&format!(
+            "<entry><title>{}</title><id>{}</id><link href=\"{}\"/>\
+             <published>{}</published><updated>{}</updated>\
+             <{element} type=\"html\">{}</{element}></entry>",
+            escape_html(&entry.title),
+            entry.url,
+            entry.url,
+            entry.published.to_rfc3339(),
+            entry.updated.to_rfc3339(),
+            escape_html(&entry_body(entry, mode))
+        ));
+    }
+    xml.push_str("</feed>");
+    xml
+}
+
+fn render_json_feed(site_url: &str, entries: &[FeedEntry], mode: ContentMode) -> String {
+    let items: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|entry| {
+            let mut item = serde_json::json!({
+                "id": entry.url,
+                "url": entry.url,
+                "title": entry.title,
+                "summary": entry.summary,
+                "date_published": entry.published.to_rfc3339(),
+                "date_modified": entry.updated.to_rfc3339(),
+            });
+            match mode {
+                ContentMode::Full => item["content_html"] = entry.content.clone().into(),
+                ContentMode::Summary => item["content_text"] = entry.summary.clone().into(),
+            }
+            item
+        })
+        .collect();
+
+    serde_json::json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": SITE_TITLE,
+        "description": SITE_DESCRIPTION,
+        "home_page_url": format!("{site_url}/"),
+        "feed_url": format!("{site_url}/feed.json"),
+        "language": "en",
+        "items": items,
+    })
+    .to_string()
+}
+
+fn http_date(timestamp: DateTime<Utc>) -> HttpDate {
+    let seconds = timestamp.timestamp().max(0) as u64;
+    HttpDate::from(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Answers with 304 when the client already holds this exact body, so
+/// aggregators polling every few minutes cost a single hash comparison.
+fn conditional_response(
+    req: &HttpRequest,
+    body: String,
+    content_type: &str,
+    last_modified: Option<DateTime<Utc>>,
+) -> HttpResponse {
+    let digest = Sha256::digest(body.as_bytes());
+    let etag = EntityTag::new_strong(hex::encode(&digest[..16]));
+    let last_modified = last_modified.map(http_date);
+
+    // If-None-Match takes precedence over If-Modified-Since when both are sent.
+    let fresh = if req.headers().contains_key(IF_NONE_MATCH) {
+        match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match (last_modified, IfModifiedSince::parse(req)) {
+            (Some(modified), Ok(IfModifiedSince(since))) => {
+                SystemTime::from(modified) <= SystemTime::from(since)
+            }
+            _ => false,
+        }
+    };
+
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(etag))
+        .insert_header((CACHE_CONTROL, "public, max-age=300"));
+    if let Some(modified) = last_modified {
+        response.insert_header(LastModified(modified));
+    }
+
+    if fresh {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
+
+async fn feed_response(
+    req: HttpRequest,
+    query: web::Query<FeedQuery>,
+    data: web::Data<AppState>,
+    render: fn(&str, &[FeedEntry], ContentMode) -> String,
+    content_type: &str,
+) -> Result<HttpResponse, ApiError> {
+    let entries = fetch_entries(&data).await?;
+    let last_modified = entries.iter().map(|e| e.updated).max();
+    let body = render(&data.config.site_url, &entries, query.mode);
+    Ok(conditional_response(&req, body, content_type, last_modified))
+}
+
+#[get("/feed.xml")]
+pub async fn rss_feed(
+    req: HttpRequest,
+    query: web::Query<FeedQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    feed_response(req, query, data, render_rss, "application/rss+xml; charset=utf-8").await
+}
+
+#[get("/atom.xml")]
+pub async fn atom_feed(
+    req: HttpRequest,
+    query: web::Query<FeedQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    feed_response(req, query, data, render_atom, "application/atom+xml; charset=utf-8").await
+}
+
+#[get("/feed.json")]
+pub async fn json_feed(
+    req: HttpRequest,
+    query: web::Query<FeedQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    feed_response(req, query, data, render_json_feed, "application/feed+json; charset=utf-8").await
+}
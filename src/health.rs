@@ -0,0 +1,192 @@
+use crate::error::ApiError;
+use crate::migrations;
+use crate::state::AppState;
+use crate::users::{AuthenticatedUser, Permission};
+use crate::STATIC_DIR;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long a readiness check may wait on the database before the instance
+/// counts as not ready.
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn from_result(result: Result<(), String>) -> Check {
+        match result {
+            Ok(()) => Check {
+                ok: true,
+                error: None,
+            },
+            Err(error) => Check {
+                ok: false,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    database: Check,
+    migrations: Check,
+    static_assets: Check,
+}
+
+impl Readiness {
+    async fn check(data: &AppState) -> Readiness {
+        let database = check_database(data).await;
+        // Without a database the migration table can't be read either.
+        let migrations = match &database {
+            Ok(()) => check_migrations(data).await,
+            Err(_) => Err("database unavailable".to_string()),
+        };
+        let static_assets = check_static_assets();
+
+        Readiness {
+            ready: database.is_ok() && migrations.is_ok() && static_assets.is_ok(),
+            database: Check::from_result(database),
+            migrations: Check::from_result(migrations),
+            static_assets: Check::from_result(static_assets),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PoolHealth {
+    size: u32,
+    idle: usize,
+    max_connections: u32,
+    /// Share of the pool's maximum that is checked out, from 0 to 1.
+    saturation: f64,
+}
+
+#[derive(Serialize)]
+struct GithubHealth {
+    ok: bool,
+    stars: i32,
+    last_fetched: DateTime<Utc>,
+    last_attempt: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BuildInfo {
+    version: &'static str,
+    commit: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct AdminHealth {
+    #[serde(flatten)]
+    readiness: Readiness,
+    pool: PoolHealth,
+    github: GithubHealth,
+    build: BuildInfo,
+}
+
+fn no_store(response: &mut actix_web::HttpResponseBuilder) -> &mut actix_web::HttpResponseBuilder {
+    response.insert_header(CacheControl(vec![CacheDirective::NoStore]))
+}
+
+async fn check_database(data: &AppState) -> Result<(), String> {
+    let ping = sqlx::query("SELECT 1").execute(&data.db);
+    match actix_web::rt::time::timeout(DATABASE_TIMEOUT, ping).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+async fn check_migrations(data: &AppState) -> Result<(), String> {
+    let pending = migrations::pending(&data.db)
+        .await
+        .map_err(|e| e.to_string())?;
+    match pending.as_slice() {
+        [] => Ok(()),
+        versions => Err(format!("pending migrations: {versions:?}")),
+    }
+}
+
+fn check_static_assets() -> Result<(), String> {
+    let index = Path::new(STATIC_DIR).join("index.html");
+    if index.is_file() {
+        Ok(())
+    } else {
+        Err(format!("{} is missing", index.display()))
+    }
+}
+
+/// Liveness: the process is up and serving requests. Nothing else is checked
+/// so a database outage doesn't get the machine restarted.
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    no_store(&mut HttpResponse::Ok()).json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Readiness: the instance can actually serve the site. Deploys should only
+/// route traffic here once this returns 200.
+#[get("/readyz")]
+pub async fn readyz(data: web::Data<AppState>) -> HttpResponse {
+    let readiness = Readiness::check(&data).await;
+    let mut response = if readiness.ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    no_store(&mut response).json(readiness)
+}
+
+/// Readiness plus connection pool usage, the last GitHub API call and the
+/// running build.
+#[get("/admin/health")]
+pub async fn admin_health(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ViewHealth)?;
+
+    let readiness = Readiness::check(&data).await;
+
+    let size = data.db.size();
+    let idle = data.db.num_idle();
+    let max_connections = data.db.options().get_max_connections();
+    let pool = PoolHealth {
+        size,
+        idle,
+        max_connections,
+        saturation: (size as usize).saturating_sub(idle) as f64 / max_connections as f64,
+    };
+
+    let github = {
+        let cache = data.star_cache.read().expect("Failed to acquire read lock");
+        GithubHealth {
+            ok: cache.last_error.is_none(),
+            stars: cache.star_count,
+            last_fetched: cache.last_fetched,
+            last_attempt: cache.last_attempt,
+            last_error: cache.last_error.clone(),
+        }
+    };
+
+    Ok(no_store(&mut HttpResponse::Ok()).json(AdminHealth {
+        readiness,
+        pool,
+        github,
+        build: BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: option_env!("GIT_COMMIT"),
+        },
+    }))
+}
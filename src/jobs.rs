@@ -0,0 +1,95 @@
+use crate::analytics;
+use crate::config::RateLimitStore;
+use crate::rate_limit;
+use crate::services;
+use crate::state::AppState;
+use crate::supervisor::Supervisor;
+use crate::views;
+use actix_web::web;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const COMMENT_EVENT_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const VIEW_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Well inside `services::STAR_CACHE_MINUTES`, so visitors are never the ones
+/// waiting on GitHub.
+const STAR_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Flips scheduled posts whose `publish_at` has passed to published.
+#[tracing::instrument(skip_all)]
+pub async fn publish_scheduled_posts(db: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE posts SET status = 'published', published_date = publish_at::date, publish_at = NULL \
+         WHERE status = 'scheduled' AND publish_at <= now()",
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Drops comment stream events older than a day; clients that far behind are
+/// told to reload rather than replay.
+async fn prune_comment_events(db: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+    let result =
+        sqlx::query("DELETE FROM comment_events WHERE created_at < now() - interval '1 day'")
+            .execute(db)
+            .await?;
+    Ok(result.rows_affected())
+}
+
+/// Hands the app's periodic jobs to the supervisor.
+pub fn register(supervisor: &mut Supervisor, data: &web::Data<AppState>) {
+    let db = data.db.clone();
+    supervisor.every("scheduled-publisher", PUBLISH_INTERVAL, move || {
+        let db = db.clone();
+        async move {
+            let count = publish_scheduled_posts(&db).await?;
+            if count > 0 {
+                tracing::info!(count, "Published scheduled posts");
+            }
+            Ok::<_, sqlx::Error>(())
+        }
+    });
+
+    // Keeps the analytics rollups current and expires old pageviews.
+    let db = data.db.clone();
+    supervisor.every("analytics-rollup", ROLLUP_INTERVAL, move || {
+        let db = db.clone();
+        async move { analytics::roll_up(&db).await }
+    });
+
+    let db = data.db.clone();
+    supervisor.every(
+        "comment-event-pruner",
+        COMMENT_EVENT_PRUNE_INTERVAL,
+        move || {
+            let db = db.clone();
+            async move { prune_comment_events(&db).await.map(drop) }
+        },
+    );
+
+    // Sessions live in the cookie, so the per-visitor state kept server-side
+    // is view dedup records and, when shared, rate limit buckets.
+    let db = data.db.clone();
+    supervisor.every("view-pruner", VIEW_PRUNE_INTERVAL, move || {
+        let db = db.clone();
+        async move { views::prune_views(&db).await.map(drop) }
+    });
+
+    if data.config.rate_limit.store == RateLimitStore::Postgres {
+        let db = data.db.clone();
+        supervisor.every("rate-limit-pruner", RATE_LIMIT_PRUNE_INTERVAL, move || {
+            let db = db.clone();
+            async move { rate_limit::prune_buckets(&db).await.map(drop) }
+        });
+    }
+
+    let data = data.clone();
+    supervisor.every("github-stars", STAR_REFRESH_INTERVAL, move || {
+        let data = data.clone();
+        async move { services::refresh_stars(&data).await.map(drop) }
+    });
+}
@@ -1,39 +1,136 @@
+mod analytics;
 mod auth;
+mod comment_stream;
+mod comments;
+mod config;
+mod error;
+mod feeds;
+mod health;
+mod jobs;
+mod markdown;
+mod metrics;
+mod migrations;
+mod moderation;
+mod pagination;
+mod preview;
+mod rate_limit;
+mod search;
 mod services;
+mod sitemap;
+mod slugs;
+mod spam;
 mod state;
+mod supervisor;
+mod taxonomy;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod users;
+mod views;
 
 use actix_files as fs;
 use actix_session::{CookieSession};
-use actix_web::{web, App, HttpServer};
+use actix_web::{middleware::from_fn, web, App, HttpServer};
 use sentry::integrations::actix;
+use analytics::{analytics_trends, record_pageview, top_analytics, Analytics};
 use auth::auth_routes;
+use comment_stream::{stream_comments, CommentStream};
+use config::{Config, RateLimitStore};
+use error::ApiError;
+use comments::{
+    create_comment, create_post_comment, edit_comment, fetch_comments,
+    fetch_post_comments, retract_comment,
+};
 use dotenv::dotenv;
+use feeds::{atom_feed, json_feed, rss_feed};
+use health::{admin_health, healthz, readyz};
+use moderation::{
+    approve_comment, bulk_delete_comments, delete_comment, flag_comment, moderation_log,
+    moderation_queue, reject_comment,
+};
+use rate_limit::{rate_limit, RateLimiter, Store};
+use search::search_content;
 use services::{
-    create_comment, fetch_comments, fetch_posts, fetch_stars, update_views, user_status,
-    create_post, update_post, delete_post, get_post_content,
+    fetch_posts, fetch_stars, update_views, user_status,
+    fetch_post, create_post, update_post, delete_post, get_post_content, serve_post, list_revisions,
+    diff_revisions, restore_revision, fetch_admin_posts, create_preview_link,
 };
 // Remove admin imports
 use sqlx::{postgres::PgPoolOptions};
+use sitemap::{robots, sitemap_index, sitemap_page};
+use slugs::blog_post_page;
+use spam::{Blocklist, SpamFilter};
 use state::AppState;
+use taxonomy::{
+    create_term, delete_term, fetch_all_series, fetch_categories, fetch_category_posts,
+    fetch_post_series, fetch_series, fetch_tag_posts, fetch_tags, update_term,
+};
+use users::{list_users, update_user_roles};
 use std::env;
-use actix_web::middleware::Logger;
+use std::time::Duration;
+use supervisor::Supervisor;
+
+/// The built frontend, served as the site root.
+const STATIC_DIR: &str = "./frontend/out";
+/// Where post bodies were kept before they moved into the database.
+const LEGACY_POSTS_DIR: &str = "./assets/posts";
+
+/// How long in-flight requests get to finish once SIGTERM arrives, then
+/// background jobs get `JOB_DRAIN_TIMEOUT` on top. Together they stay under
+/// `kill_timeout` in fly.toml.
+const REQUEST_DRAIN_TIMEOUT: Duration = Duration::from_secs(20);
+const JOB_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 async fn index() -> std::io::Result<fs::NamedFile> {
-    fs::NamedFile::open("./frontend/out/index.html")
+    fs::NamedFile::open(format!("{STATIC_DIR}/index.html"))
 }
 
-async fn not_found() -> actix_web::HttpResponse {
-    actix_web::HttpResponse::NotFound().body("404 Not Found")
+async fn not_found() -> Result<actix_web::HttpResponse, ApiError> {
+    Err(ApiError::not_found("Page not found"))
 }
 
 // Remove tools_page function since it should be handled by JavaScript routing
 
+/// `rayspace_rs migrate <up|down|status>` manages the schema and exits. It
+/// only needs the database, so the rest of the configuration may be missing.
+fn migrate(args: &[String]) -> std::io::Result<()> {
+    let database_url = config::database_url().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration:\n{e}");
+        std::process::exit(1);
+    });
+    actix_web::rt::System::new().block_on(async {
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+            .expect("Error building a connection pool");
+        if let Err(e) = migrations::command(&pool, args).await {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        Ok(())
+    })
+}
+
 fn main() -> std::io::Result<()> {
-    env_logger::init();
     dotenv().ok();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(("migrate", args)) = args.split_first().map(|(cmd, rest)| (cmd.as_str(), rest)) {
+        return migrate(args);
+    }
+
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration:\n{e}");
+        std::process::exit(1);
+    });
+    let _telemetry = telemetry::init(&config).unwrap_or_else(|e| {
+        eprintln!("Failed to set up logging:\n{e}");
+        std::process::exit(1);
+    });
     let _guard = sentry::init((
-        "https://3a92ba62a6165a73da3081b74837a14c@o4509686868017152.ingest.us.sentry.io/4509686883090432",
+        config.sentry_dsn.as_deref(),
         sentry::ClientOptions {
+            environment: Some(config.environment.clone().into()),
             send_default_pii: true,
             traces_sample_rate: 1.0,
             ..Default::default()
@@ -41,27 +138,85 @@ fn main() -> std::io::Result<()> {
     ));
 
     actix_web::rt::System::new().block_on(async {
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
         let pool = PgPoolOptions::new()
             .max_connections(12)
-            .connect(&database_url)
+            .connect(&config.database_url)
             .await
             .expect("Error building a connection pool");
-        let github_client_id =
-            env::var("GITHUB_CLIENT_ID").expect("Missing the GITHUB_CLIENT_ID environment variable.");
-        let github_client_secret = env::var("GITHUB_CLIENT_SECRET")
-            .expect("Missing the GITHUB_CLIENT_SECRET environment variable.");
-        let secret_key_hex = env::var("SECRET_KEY").expect("SECRET_KEY must be set");
-        let secret_key =
-            hex::decode(secret_key_hex).expect("SECRET_KEY must be a hex-encoded byte array");
-        let app_state = web::Data::new(AppState::new(github_client_id, github_client_secret, pool));
-
-        // Get port from environment or default to 8080 for Fly.io
-        let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
-        let addr = format!("0.0.0.0:{port}");
-
-        HttpServer::new(move || {
+
+        migrations::run(&pool)
+            .await
+            .expect("Failed to apply database migrations");
+        services::import_legacy_posts(&pool, LEGACY_POSTS_DIR)
+            .await
+            .expect("Failed to import legacy post bodies");
+
+        let blocklist = match &config.spam.blocklist_file {
+            Some(path) => Blocklist::load(path).expect("spam.blocklist_file could not be loaded"),
+            None => Blocklist::empty(),
+        };
+        let spam_filter = SpamFilter::new(
+            blocklist,
+            config.spam.queue_score,
+            config.spam.reject_score,
+        );
+
+        let rate_limit_store = match config.rate_limit.store {
+            RateLimitStore::Postgres => Store::Postgres(pool.clone()),
+            RateLimitStore::Memory => Store::memory(),
+        };
+        if config.environment == "production" && config.rate_limit.trusted_proxies.is_empty() {
+            tracing::warn!(
+                "TRUSTED_PROXIES is empty, so clients behind a proxy share its address for rate limits and view counts"
+            );
+        }
+        let rate_limiter = web::Data::new(RateLimiter::new(
+            rate_limit_store,
+            config.rate_limit.trusted_proxies.clone(),
+        ));
+
+        let analytics = web::Data::new(
+            Analytics::new(config.geoip_database.as_deref())
+                .expect("analytics.geoip_database could not be loaded"),
+        );
+
+        let secret_key = config.secret_key.clone();
+        let addr = format!("0.0.0.0:{}", config.port);
+        // With its own port, `/metrics` stays off the public listener.
+        let metrics_addr = config.metrics.port.map(|port| format!("0.0.0.0:{port}"));
+        let public_metrics = metrics_addr.is_none() && config.metrics.token.is_some();
+        let app_state = web::Data::new(AppState::new(config, pool.clone(), spam_filter));
+
+        let mut supervisor = Supervisor::new();
+        let comment_stream = web::Data::new(CommentStream::start(pool.clone(), &mut supervisor));
+        jobs::register(&mut supervisor, &app_state);
+
+        // Signals are handled below rather than by actix, so that the servers
+        // and the supervisor stop together.
+        let metrics_server = match metrics_addr {
+            Some(metrics_addr) => {
+                let app_state = app_state.clone();
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(app_state.clone())
+                        .service(metrics::metrics)
+                })
+                .workers(1)
+                .disable_signals()
+                .bind(metrics_addr)?
+                .run();
+                let handle = server.handle();
+                actix_web::rt::spawn(server);
+                Some(handle)
+            }
+            None => None,
+        };
+
+        let server = HttpServer::new(move || {
             App::new()
+                .wrap(from_fn(rate_limit))
+                .wrap(from_fn(telemetry::request_id))
+                .wrap(from_fn(metrics::track_requests))
                 .wrap(
                     actix::Sentry::builder()
                         .capture_server_errors(true)
@@ -73,40 +228,110 @@ fn main() -> std::io::Result<()> {
                         .secure(true)
                         .name("User"),
                 )
-                .wrap(
-                    Logger::new("%t %a \"%r\" %s %b %T \"%{User-Agent}i\"")
-                        .exclude_regex(r"^/(styles|images|scripts)/.*")
-                )
                 .app_data(app_state.clone())
+                .app_data(rate_limiter.clone())
+                .app_data(analytics.clone())
+                .app_data(comment_stream.clone())
+                .app_data(web::JsonConfig::default().error_handler(error::extractor_error))
+                .app_data(web::QueryConfig::default().error_handler(error::extractor_error))
+                .app_data(web::PathConfig::default().error_handler(error::extractor_error))
                 .service(auth_routes())
                 .service(
                     web::scope("/api")
                         .service(fetch_posts)
+                        .service(fetch_post)
+                        .service(search_content)
                         .service(fetch_comments)
+                        .service(stream_comments)
                         .service(create_comment)
+                        .service(edit_comment)
+                        .service(retract_comment)
+                        .service(flag_comment)
+                        .service(fetch_post_comments)
+                        .service(create_post_comment)
                         .service(update_views)
+                        .service(record_pageview)
                         .service(user_status)
                         .service(fetch_stars)
+                        .service(fetch_admin_posts)
                         .service(create_post)
                         .service(update_post)
                         .service(delete_post)
                         .service(get_post_content)
+                        .service(list_revisions)
+                        .service(diff_revisions)
+                        .service(restore_revision)
+                        .service(create_preview_link)
+                        .service(moderation_queue)
+                        .service(moderation_log)
+                        .service(approve_comment)
+                        .service(reject_comment)
+                        .service(bulk_delete_comments)
+                        .service(delete_comment)
+                        .service(list_users)
+                        .service(update_user_roles)
+                        .service(top_analytics)
+                        .service(analytics_trends)
+                        .service(admin_health)
+                        .service(fetch_tags)
+                        .service(fetch_tag_posts)
+                        .service(fetch_categories)
+                        .service(fetch_category_posts)
+                        .service(fetch_all_series)
+                        .service(fetch_series)
+                        .service(fetch_post_series)
+                        .service(create_term)
+                        .service(update_term)
+                        .service(delete_term)
                 )
+                .service(healthz)
+                .service(readyz)
+                .configure(|cfg| {
+                    if public_metrics {
+                        cfg.service(metrics::metrics);
+                    }
+                })
                 .route("/tools", web::get().to(not_found))
                 // Remove the /tools route - let JavaScript handle it
+                .service(serve_post)
+                .service(blog_post_page)
+                .service(rss_feed)
+                .service(atom_feed)
+                .service(json_feed)
+                .service(sitemap_index)
+                .service(sitemap_page)
+                .service(robots)
                 .service(
-                    fs::Files::new("/posts", "./assets/posts")
-                        .use_last_modified(true),
-                )
-                .service(
-                    fs::Files::new("/", "./frontend/out")
+                    fs::Files::new("/", STATIC_DIR)
                         .index_file("index.html")
                         .use_last_modified(true),
                 )
                 .default_service(web::route().to(index))
         })
+        .disable_signals()
+        .shutdown_timeout(REQUEST_DRAIN_TIMEOUT.as_secs())
         .bind(addr)?
-        .run()
-        .await
+        .run();
+        let handle = server.handle();
+        let server = actix_web::rt::spawn(server);
+
+        // On SIGTERM: stop accepting connections and let in-flight requests
+        // finish, then wait for running jobs, then close the pool.
+        let result = tokio::select! {
+            result = server => result.expect("the server task panicked"),
+            _ = supervisor::termination() => {
+                tracing::info!("Shutting down");
+                supervisor.stop();
+                if let Some(metrics_server) = &metrics_server {
+                    metrics_server.stop(true).await;
+                }
+                handle.stop(true).await;
+                Ok(())
+            }
+        };
+        supervisor.join(JOB_DRAIN_TIMEOUT).await;
+        pool.close().await;
+        tracing::info!("Shutdown complete");
+        result
     })
 }
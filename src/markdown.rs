@@ -0,0 +1,295 @@
+use pulldown_cmark::{html, CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::collections::HashSet;
+
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub toc: String,
+    pub text: String,
+}
+
+struct Heading {
+    level: u8,
+    text: String,
+    slug: String,
+}
+
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Strips tags from sanitized HTML and decodes the entities ammonia emits,
+/// collapsing whitespace.
+pub fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    let mut last_space = true;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if in_tag => {}
+            c if c.is_whitespace() => {
+                if !last_space {
+                    text.push(' ');
+                    last_space = true;
+                }
+            }
+            c => {
+                text.push(c);
+                last_space = false;
+            }
+        }
+    }
+
+    text.trim_end()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH
+}
+
+fn heading_level(level: HeadingLevel) -> u8 {
+    match level {
+        HeadingLevel::H1 => 1,
+        HeadingLevel::H2 => 2,
+        HeadingLevel::H3 => 3,
+        HeadingLevel::H4 => 4,
+        HeadingLevel::H5 => 5,
+        HeadingLevel::H6 => 6,
+    }
+}
+
+fn sanitizer() -> ammonia::Builder<'static> {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_tags(&["input"])
+        .add_tag_attributes("input", &["type", "checked", "disabled"])
+        .add_tag_attributes("a", &["class", "aria-hidden"])
+        .add_tag_attributes("code", &["class"])
+        .add_tag_attributes("sup", &["class"])
+        .add_tag_attributes("div", &["class", "id"]);
+    for tag in ["h1", "h2", "h3", "h4", "h5", "h6"] {
+        builder.add_tag_attributes(tag, &["id"]);
+    }
+    builder
+}
+
+/// Renders CommonMark + GFM extensions to sanitized HTML, giving every heading a
+/// unique anchor id and collecting them into a table of contents.
+pub fn render(source: &str) -> RenderedMarkdown {
+    let mut events: Vec<Event> = Parser::new_ext(source, options()).collect();
+    let text = extract_text(&events);
+
+    let mut headings = Vec::new();
+    let mut used = HashSet::new();
+    let mut index = 0;
+    while index < events.len() {
+        if let Event::Start(Tag::Heading { level, .. }) = &events[index] {
+            let level = heading_level(*level);
+            let mut text = String::new();
+            let mut end = index + 1;
+            while end < events.len() {
+                match &events[end] {
+                    Event::End(TagEnd::Heading(_)) => break,
+                    Event::Text(t) | Event::Code(t) => text.push_str(t),
+                    _ => {}
+                }
+                end += 1;
+            }
+
+            let base = match slugify(&text) {
+                s if s.is_empty() => String::from("section"),
+                s => s,
+            };
+            let mut slug = base.clone();
+            let mut suffix = 1;
+            while !used.insert(slug.clone()) {
+                slug = format!("{base}-{suffix}");
+                suffix += 1;
+            }
+
+            if let Event::Start(Tag::Heading { id, .. }) = &mut events[index] {
+                *id = Some(CowStr::from(slug.clone()));
+            }
+            events.insert(
+                index + 1,
+                Event::InlineHtml(CowStr::from(format!(
+                    "<a class=\"heading-anchor\" href=\"#{slug}\" aria-hidden=\"true\">#</a>"
+                ))),
+            );
+            headings.push(Heading { level, text, slug });
+            index = end + 1;
+        } else {
+            index += 1;
+        }
+    }
+
+    let mut unsafe_html = String::with_capacity(source.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, events.into_iter());
+
+    RenderedMarkdown {
+        html: sanitizer().clean(&unsafe_html).to_string(),
+        toc: render_toc(&headings),
+        text,
+    }
+}
+
+/// Visible text of the document with block boundaries turned into spaces,
+/// used for search indexing and feed summaries.
+fn extract_text(events: &[Event]) -> String {
+    let mut text = String::new();
+    for event in events {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(t),
+            // Raw HTML blocks arrive a line at a time.
+            Event::Html(html) => {
+                text.push_str(&plain_text(html));
+                text.push(' ');
+            }
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(tag)
+                if !matches!(
+                    tag,
+                    TagEnd::Emphasis
+                        | TagEnd::Strong
+                        | TagEnd::Strikethrough
+                        | TagEnd::Link
+                        | TagEnd::Image
+                ) =>
+            {
+                text.push(' ')
+            }
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn render_toc(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return String::new();
+    }
+
+    let base = headings.iter().map(|h| h.level).min().unwrap_or(1);
+    let mut toc = String::from("<nav class='post-toc'><ul>");
+    let mut depth = 0;
+    for (i, heading) in headings.iter().enumerate() {
+        // Never skip a nesting level, so "## then ####" still produces valid lists.
+        let target = if i == 0 {
+            0
+        } else {
+            ((heading.level - base) as usize).min(depth + 1)
+        };
+        if i > 0 {
+            if target > depth {
+                toc.push_str("<ul>");
+            } else {
+                toc.push_str("</li>");
+                for _ in target..depth {
+                    toc.push_str("</ul></li>");
+                }
+            }
+        }
+        depth = target;
+        toc.push_str(&format!(
+            "<li><a href='#{}'>{}</a>",
+            heading.slug,
+            escape_html(&heading.text)
+        ));
+    }
+    toc.push_str("</li>");
+    for _ in 0..depth {
+        toc.push_str("</ul></li>");
+    }
+    toc.push_str("</ul></nav>");
+    toc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anchor_ids(html: &str) -> Vec<&str> {
+        html.split(" id=\"")
+            .skip(1)
+            .map(|rest| rest.split('"').next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn slugify_collapses_punctuation() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  --Rust & WebAssembly--  "), "rust-webassembly");
+        assert_eq!(slugify("Ünïcode Straße"), "ünïcode-straße");
+        assert_eq!(slugify("?!"), "");
+    }
+
+    #[test]
+    fn duplicate_headings_get_unique_anchors() {
+        let rendered = render("# Intro\n\n## Intro\n\n## Intro 1\n\n### Intro\n");
+        assert_eq!(
+            anchor_ids(&rendered.html),
+            vec!["intro", "intro-1", "intro-1-1", "intro-2"]
+        );
+        assert!(rendered.toc.contains("href='#intro-1-1'"));
+        assert!(rendered.html.contains("href=\"#intro-2\""));
+    }
+
+    #[test]
+    fn headings_without_text_become_sections() {
+        let rendered = render("# ???\n\n# !!!\n");
+        assert_eq!(anchor_ids(&rendered.html), vec!["section", "section-1"]);
+    }
+
+    #[test]
+    fn toc_nests_without_skipping_levels() {
+        let rendered = render("## A\n\n#### B\n\n## C & D\n");
+        assert_eq!(
+            rendered.toc,
+            "<nav class='post-toc'><ul><li><a href='#a'>A</a><ul>\
+             <li><a href='#b'>B</a></li></ul></li>\
+             <li><a href='#c-d'>C &amp; D</a></li></ul></nav>"
+        );
+        assert_eq!(render("No headings here.").toc, "");
+    }
+
+    #[test]
+    fn text_keeps_words_apart() {
+        let rendered = render("# Title\n\nSome *emphasised* text.\n\n- one\n- two\n");
+        assert_eq!(rendered.text, "Title Some emphasised text. one two");
+    }
+}
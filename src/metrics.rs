@@ -0,0 +1,214 @@
+use crate::error::ApiError;
+use crate::moderation::CommentStatus;
+use crate::state::AppState;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use actix_web::middleware::Next;
+use actix_web::{get, web, Error, HttpRequest, HttpResponse};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::time::{Duration, Instant};
+
+/// Label for requests that matched no route (static files, the SPA fallback
+/// and 404s), so stray paths can't create new series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Prometheus collectors for the whole app, exported as `rayspace_*`.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_max_connections: IntGauge,
+    posts_created: IntCounter,
+    comments_created: IntCounterVec,
+    star_cache: IntCounterVec,
+    upstream_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("rayspace".to_string()), None)
+            .expect("the metrics prefix is valid");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .expect("valid metric");
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests",
+            ),
+            &["method", "route"],
+        )
+        .expect("valid metric");
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database connections by state"),
+            &["state"],
+        )
+        .expect("valid metric");
+        let pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Upper bound on the database pool size",
+        )
+        .expect("valid metric");
+        let posts_created =
+            IntCounter::new("posts_created_total", "Posts created").expect("valid metric");
+        let comments_created = IntCounterVec::new(
+            Opts::new(
+                "comments_created_total",
+                "Comments created, by the status they were given",
+            ),
+            &["status"],
+        )
+        .expect("valid metric");
+        let star_cache = IntCounterVec::new(
+            Opts::new(
+                "github_star_cache_requests_total",
+                "Star count requests answered from the cache (hit) or GitHub (miss)",
+            ),
+            &["result"],
+        )
+        .expect("valid metric");
+        let upstream_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Time spent waiting on other services",
+            ),
+            &["service", "operation", "outcome"],
+        )
+        .expect("valid metric");
+
+        for collector in [
+            Box::new(http_requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(http_duration.clone()),
+            Box::new(pool_connections.clone()),
+            Box::new(pool_max_connections.clone()),
+            Box::new(posts_created.clone()),
+            Box::new(comments_created.clone()),
+            Box::new(star_cache.clone()),
+            Box::new(upstream_duration.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+
+        Metrics {
+            registry,
+            http_requests,
+            http_duration,
+            pool_connections,
+            pool_max_connections,
+            posts_created,
+            comments_created,
+            star_cache,
+            upstream_duration,
+        }
+    }
+
+    pub fn post_created(&self) {
+        self.posts_created.inc();
+    }
+
+    pub fn comment_created(&self, status: CommentStatus) {
+        self.comments_created
+            .with_label_values(&[status.as_str()])
+            .inc();
+    }
+
+    pub fn star_cache(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.star_cache.with_label_values(&[result]).inc();
+    }
+
+    /// Records a call to the GitHub API.
+    pub fn github_call(&self, operation: &str, elapsed: Duration, ok: bool) {
+        let outcome = if ok { "ok" } else { "error" };
+        self.upstream_duration
+            .with_label_values(&["github", operation, outcome])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Everything in the text exposition format. Pool gauges are sampled
+    /// now rather than tracked as connections come and go.
+    fn render(&self, db: &Pool<Postgres>) -> Result<String, prometheus::Error> {
+        let size = db.size() as i64;
+        let idle = db.num_idle() as i64;
+        self.pool_connections.with_label_values(&["idle"]).set(idle);
+        self.pool_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+        self.pool_max_connections
+            .set(db.options().get_max_connections() as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// Counts and times every request by its route pattern.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return next.call(req).await;
+    };
+    let started = Instant::now();
+    let method = req.method().to_string();
+
+    let res = next.call(req).await?;
+    let route = res
+        .request()
+        .match_pattern()
+        .filter(|route| !route.is_empty());
+    let route = route.as_deref().unwrap_or(UNMATCHED_ROUTE);
+    let status = res.status();
+    data.metrics
+        .http_requests
+        .with_label_values(&[&method, route, status.as_str()])
+        .inc();
+    data.metrics
+        .http_duration
+        .with_label_values(&[&method, route])
+        .observe(started.elapsed().as_secs_f64());
+    Ok(res)
+}
+
+/// Whether the request carries `Authorization: Bearer <token>`. Hashing both
+/// sides keeps the comparison from leaking the token a byte at a time.
+fn has_token(req: &HttpRequest, token: &str) -> bool {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| Sha256::digest(given.trim()) == Sha256::digest(token))
+}
+
+/// Prometheus scrape endpoint. Only mounted when `metrics.token` or
+/// `metrics.port` is configured; with a token set it is required on either.
+#[get("/metrics")]
+pub async fn metrics(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(token) = &data.config.metrics.token {
+        if !has_token(&req, token) {
+            return Err(ApiError::forbidden("A valid metrics token is required"));
+        }
+    }
+
+    let body = data.metrics.render(&data.db).map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, TextEncoder::new().format_type()))
+        .body(body))
+}
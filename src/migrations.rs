@@ -0,0 +1,87 @@
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+
+/// Migrations from `migrations/`, embedded at compile time. Applying or
+/// reverting them takes a Postgres advisory lock, so instances booting at the
+/// same time don't race each other.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+const USAGE: &str = "usage: rayspace_rs migrate <up | down [target_version] | status>";
+
+/// Brings the schema up to date; called on every boot.
+#[tracing::instrument(skip_all)]
+pub async fn run(db: &Pool<Postgres>) -> Result<(), MigrateError> {
+    MIGRATOR.run(db).await
+}
+
+async fn applied_versions(db: &Pool<Postgres>) -> Result<HashMap<i64, Vec<u8>>, MigrateError> {
+    let mut conn = db.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum.into_owned()))
+        .collect())
+}
+
+/// Versions embedded in this build that the database hasn't applied yet.
+pub async fn pending(db: &Pool<Postgres>) -> Result<Vec<i64>, MigrateError> {
+    let applied = applied_versions(db).await?;
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains_key(version))
+        .collect())
+}
+
+/// Reverts to `target`, or undoes only the latest migration when none is
+/// given.
+async fn down(db: &Pool<Postgres>, target: Option<i64>) -> Result<(), MigrateError> {
+    let target = match target {
+        Some(target) => target,
+        None => {
+            let mut applied: Vec<i64> = applied_versions(db).await?.into_keys().collect();
+            applied.sort_unstable();
+            applied.pop();
+            applied.pop().unwrap_or(0)
+        }
+    };
+    MIGRATOR.undo(db, target).await
+}
+
+async fn status(db: &Pool<Postgres>) -> Result<(), MigrateError> {
+    let applied = applied_versions(db).await?;
+    for migration in MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+    {
+        let state = match applied.get(&migration.version) {
+            Some(checksum) if *checksum == *migration.checksum => "applied",
+            Some(_) => "applied (modified since)",
+            None => "pending",
+        };
+        println!(
+            "{:>4} {:<24} {state}",
+            migration.version, migration.description
+        );
+    }
+    Ok(())
+}
+
+/// Handles `rayspace_rs migrate ...` without starting the server.
+pub async fn command(db: &Pool<Postgres>, args: &[String]) -> std::io::Result<()> {
+    let result = match args {
+        [command] if command == "up" => run(db).await,
+        [command] if command == "down" => down(db, None).await,
+        [command, target] if command == "down" => match target.parse() {
+            Ok(target) => down(db, Some(target)).await,
+            Err(_) => return Err(std::io::Error::other(USAGE)),
+        },
+        [command] if command == "status" => status(db).await,
+        _ => return Err(std::io::Error::other(USAGE)),
+    };
+    result.map_err(std::io::Error::other)
+}
@@ -0,0 +1,384 @@
+use crate::error::ApiError;
+use crate::pagination::{Cursor, Page, PageQuery};
+use crate::spam::{self, Decision, Label, Verdict};
+use crate::state::AppState;
+use crate::users::{AuthenticatedUser, Permission};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::FromRow;
+
+/// Comment statuses readers can see. Flagged comments stay up until a
+/// moderator reviews them; pending and rejected ones are hidden.
+pub(crate) const VISIBLE_COMMENT_FILTER: &str = "c.status IN ('approved', 'flagged')";
+
+const MAX_BULK_DELETE: usize = 100;
+
+/// How new comments are admitted, set with `COMMENT_MODERATION`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModerationMode {
+    /// Every comment goes live immediately.
+    AutoApprove,
+    /// A user's comments are held until one of them has been approved.
+    FirstTime,
+    /// Every comment waits for a moderator.
+    All,
+}
+
+impl ModerationMode {
+    pub fn parse(value: &str) -> Option<ModerationMode> {
+        match value {
+            "auto" => Some(ModerationMode::AutoApprove),
+            "first_time" => Some(ModerationMode::FirstTime),
+            "all" => Some(ModerationMode::All),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum CommentStatus {
+    Pending,
+    Approved,
+    Flagged,
+    Rejected,
+}
+
+impl CommentStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CommentStatus::Pending => "pending",
+            CommentStatus::Approved => "approved",
+            CommentStatus::Flagged => "flagged",
+            CommentStatus::Rejected => "rejected",
+        }
+    }
+
+    /// Status of an edited comment, given what screening the new text gave.
+    /// An edit can make a comment stricter but never undoes a hold: only a
+    /// moderator can approve a pending or flagged comment.
+    pub fn after_edit(self, screened: CommentStatus) -> CommentStatus {
+        match (self, screened) {
+            (_, CommentStatus::Rejected) => CommentStatus::Rejected,
+            (CommentStatus::Approved, screened) => screened,
+            (current, _) => current,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueFilter {
+    Pending,
+    Flagged,
+}
+
+#[derive(Deserialize)]
+pub struct QueueQuery {
+    pub status: Option<QueueFilter>,
+}
+
+#[derive(Deserialize)]
+pub struct BulkDelete {
+    pub ids: Vec<i32>,
+}
+
+/// A comment as moderators see it: unmasked, including retracted text.
+#[derive(Serialize, FromRow)]
+struct QueuedComment {
+    id: i32,
+    userid: String,
+    name: String,
+    comment: String,
+    timestamp: DateTime<Utc>,
+    status: CommentStatus,
+    post_id: Option<i32>,
+    parent_id: Option<i32>,
+    edited_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    spam_score: Option<f32>,
+    spam_rules: Option<Json<serde_json::Value>>,
+}
+
+#[derive(Serialize, FromRow)]
+struct ModerationAction {
+    id: i32,
+    comment_id: i32,
+    moderator_id: String,
+    action: String,
+    created_at: DateTime<Utc>,
+}
+
+/// Status for a comment that was just written or edited by `user`, plus the
+/// spam verdict behind it. Moderators and admins skip both checks.
+#[tracing::instrument(skip_all, fields(user_id = %user.id, comment_id = ?comment_id))]
+pub async fn screen_comment(
+    user: &AuthenticatedUser,
+    comment_id: Option<i32>,
+    text: &str,
+    data: &AppState,
+) -> Result<(CommentStatus, Option<Verdict>), sqlx::Error> {
+    if user.can(Permission::ModerateComments) {
+        return Ok((CommentStatus::Approved, None));
+    }
+
+    let verdict = data
+        .spam
+        .evaluate(&data.db, &user.id, comment_id, text)
+        .await?;
+    let status = match verdict.decision {
+        Decision::Reject => CommentStatus::Rejected,
+        Decision::Queue => CommentStatus::Pending,
+        Decision::Accept => match data.config.comments.moderation {
+            ModerationMode::AutoApprove => CommentStatus::Approved,
+            ModerationMode::All => CommentStatus::Pending,
+            // The comment being edited doesn't count towards its own author's
+            // history.
+            ModerationMode::FirstTime => {
+                let trusted: bool = sqlx::query_scalar(
+                    "SELECT EXISTS (SELECT 1 FROM comments WHERE userid = $1 AND status = 'approved' \
+                     AND id IS DISTINCT FROM $2)",
+                )
+                .bind(&user.id)
+                .bind(comment_id)
+                .fetch_one(&data.db)
+                .await?;
+                if trusted {
+                    CommentStatus::Approved
+                } else {
+                    CommentStatus::Pending
+                }
+            }
+        },
+    };
+    Ok((status, Some(verdict)))
+}
+
+async fn log_action(
+    executor: impl sqlx::PgExecutor<'_>,
+    comment_ids: &[i32],
+    moderator: &AuthenticatedUser,
+    action: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO comment_moderation_log (comment_id, moderator_id, action) \
+         SELECT id, $2, $3 FROM UNNEST($1::int[]) AS id",
+    )
+    .bind(comment_ids)
+    .bind(&moderator.id)
+    .bind(action)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Comments waiting for a decision, newest first.
+#[get("/admin/comments")]
+pub async fn moderation_queue(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    query: web::Query<QueueQuery>,
+    page: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ModerateComments)?;
+    let cursor = page.cursor::<DateTime<Utc>, i32>()?;
+    let limit = page.limit();
+    let (keyset, order) = Cursor::sql(cursor.as_ref(), "c.timestamp", "c.id", 3);
+
+    let statuses: &[&str] = match query.status {
+        Some(QueueFilter::Pending) => &["pending"],
+        Some(QueueFilter::Flagged) => &["flagged"],
+        None => &["pending", "flagged"],
+    };
+
+    let sql = format!(
+        "SELECT c.id, c.userid, c.name, c.comment, c.timestamp, c.status, c.post_id, c.parent_id, \
+         c.edited_at, c.deleted_at, v.score AS spam_score, v.rules AS spam_rules \
+         FROM comments c LEFT JOIN comment_spam_verdicts v ON v.comment_id = c.id \
+         WHERE c.status = ANY($1) AND {keyset} ORDER BY {order} LIMIT $2"
+    );
+    let mut comments = sqlx::query_as::<_, QueuedComment>(&sql)
+        .bind(statuses)
+        .bind(limit + 1);
+    if let Some(cursor) = &cursor {
+        comments = comments.bind(cursor.key).bind(cursor.id);
+    }
+
+    let comments = comments.fetch_all(&data.db).await?;
+    Ok(HttpResponse::Ok().json(Page::new(
+        &req,
+        limit,
+        cursor.as_ref(),
+        comments,
+        |comment| (comment.timestamp, comment.id),
+    )))
+}
+
+async fn set_status(
+    user: &AuthenticatedUser,
+    comment_id: i32,
+    status: CommentStatus,
+    action: &str,
+    data: &AppState,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ModerateComments)?;
+
+    let mut tx = data.db.begin().await?;
+    let updated = sqlx::query("UPDATE comments SET status = $1 WHERE id = $2")
+        .bind(status)
+        .bind(comment_id)
+        .execute(&mut *tx)
+        .await?;
+    if updated.rows_affected() == 0 {
+        return Err(ApiError::not_found("Comment not found"));
+    }
+    // Every decision teaches the classifier what this site considers spam.
+    let label = match status {
+        CommentStatus::Rejected => Some(Label::Spam),
+        CommentStatus::Approved => Some(Label::Ham),
+        CommentStatus::Pending | CommentStatus::Flagged => None,
+    };
+    if let Some(label) = label {
+        spam::train(&mut tx, comment_id, label).await?;
+    }
+    log_action(&mut *tx, &[comment_id], user, action).await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": comment_id, "status": status })))
+}
+
+#[post("/admin/comments/{id}/approve")]
+pub async fn approve_comment(
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    set_status(
+        &user,
+        path.into_inner(),
+        CommentStatus::Approved,
+        "approve",
+        &data,
+    )
+    .await
+}
+
+#[post("/admin/comments/{id}/reject")]
+pub async fn reject_comment(
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    set_status(
+        &user,
+        path.into_inner(),
+        CommentStatus::Rejected,
+        "reject",
+        &data,
+    )
+    .await
+}
+
+/// Lets any signed-in reader report a visible comment for review.
+#[post("/comments/{id}/flag")]
+pub async fn flag_comment(
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::Comment)?;
+
+    let result = sqlx::query(
+        "UPDATE comments SET status = 'flagged' \
+         WHERE id = $1 AND status = 'approved' AND deleted_at IS NULL",
+    )
+    .bind(path.into_inner())
+    .execute(&data.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("Comment not found"));
+    }
+    Ok(HttpResponse::Ok().json("Comment flagged for review"))
+}
+
+async fn delete_comments(
+    user: &AuthenticatedUser,
+    ids: &[i32],
+    data: &AppState,
+) -> Result<Vec<i32>, sqlx::Error> {
+    // Replies are removed with their parent by ON DELETE CASCADE.
+    let mut tx = data.db.begin().await?;
+    let deleted: Vec<i32> =
+        sqlx::query_scalar("DELETE FROM comments WHERE id = ANY($1) RETURNING id")
+            .bind(ids)
+            .fetch_all(&mut *tx)
+            .await?;
+    log_action(&mut *tx, &deleted, user, "delete").await?;
+    tx.commit().await?;
+    Ok(deleted)
+}
+
+#[delete("/admin/comments/{id}")]
+pub async fn delete_comment(
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ModerateComments)?;
+
+    let deleted = delete_comments(&user, &[path.into_inner()], &data).await?;
+    if deleted.is_empty() {
+        return Err(ApiError::not_found("Comment not found"));
+    }
+    Ok(HttpResponse::Ok().json("Comment deleted successfully"))
+}
+
+#[post("/admin/comments/bulk_delete")]
+pub async fn bulk_delete_comments(
+    user: AuthenticatedUser,
+    body: web::Json<BulkDelete>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ModerateComments)?;
+    if body.ids.is_empty() || body.ids.len() > MAX_BULK_DELETE {
+        return Err(ApiError::invalid("Provide between 1 and 100 comment ids"));
+    }
+
+    let deleted = delete_comments(&user, &body.ids, &data).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "deleted": deleted })))
+}
+
+#[get("/admin/comments/log")]
+pub async fn moderation_log(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ModerateComments)?;
+    let cursor = query.cursor::<DateTime<Utc>, i32>()?;
+    let limit = query.limit();
+    let (keyset, order) = Cursor::sql(cursor.as_ref(), "created_at", "id", 2);
+
+    let sql = format!(
+        "SELECT id, comment_id, moderator_id, action, created_at FROM comment_moderation_log \
+         WHERE {keyset} ORDER BY {order} LIMIT $1"
+    );
+    let mut actions = sqlx::query_as::<_, ModerationAction>(&sql).bind(limit + 1);
+    if let Some(cursor) = &cursor {
+        actions = actions.bind(cursor.key).bind(cursor.id);
+    }
+
+    let actions = actions.fetch_all(&data.db).await?;
+    Ok(HttpResponse::Ok().json(Page::new(
+        &req,
+        limit,
+        cursor.as_ref(),
+        actions,
+        |action| (action.created_at, action.id),
+    )))
+}
@@ -0,0 +1,260 @@
+use crate::error::ApiError;
+use actix_web::HttpRequest;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+impl PageQuery {
+    /// The requested page size, clamped to `1..=MAX_LIMIT`.
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    pub fn cursor<K: Serialize + DeserializeOwned, I: Serialize + DeserializeOwned>(
+        &self,
+    ) -> Result<Option<Cursor<K, I>>, ApiError> {
+        self.cursor.as_deref().map(Cursor::decode).transpose()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "n")]
+    Next,
+    #[serde(rename = "p")]
+    Prev,
+}
+
+/// A position in a list ordered newest first by `(key, id)`. Clients get it
+/// as an opaque string and hand it back unchanged. The id is the row's
+/// integer primary key unless the table has another unique tie-breaker.
+#[derive(Serialize, Deserialize)]
+pub struct Cursor<K, I = i32> {
+    #[serde(rename = "k")]
+    pub key: K,
+    pub id: I,
+    #[serde(rename = "d")]
+    pub direction: Direction,
+}
+
+impl<K: Serialize + DeserializeOwned, I: Serialize + DeserializeOwned> Cursor<K, I> {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursors serialize"))
+    }
+
+    fn decode(value: &str) -> Result<Cursor<K, I>, ApiError> {
+        URL_SAFE_NO_PAD
+            .decode(value)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| ApiError::invalid("Invalid cursor"))
+    }
+
+    /// Keyset condition and ordering over `key_column, id_column`, with the
+    /// cursor's key and id bound at `$first_param` and the next placeholder.
+    /// Pages before the cursor are fetched in ascending order and flipped
+    /// back by [`Page::new`].
+    pub fn sql(
+        cursor: Option<&Cursor<K, I>>,
+        key_column: &str,
+        id_column: &str,
+        first_param: usize,
+    ) -> (String, String) {
+        let (comparison, order) = match cursor.map(|cursor| cursor.direction) {
+            None => {
+                return (
+                    "TRUE".to_string(),
+                    format!("{key_column} DESC, {id_column} DESC"),
+                )
+            }
+            Some(Direction::Next) => ("<", "DESC"),
+            Some(Direction::Prev) => (">", "ASC"),
+        };
+        (
+            format!(
+                "({key_column}, {id_column}) {comparison} (${first_param}, ${})",
+                first_param + 1
+            ),
+            format!("{key_column} {order}, {id_column} {order}"),
+        )
+    }
+}
+
+/// One page of a list plus links to its neighbours, `null` at either end.
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from rows fetched with `LIMIT limit + 1` in the order
+    /// given by [`Cursor::sql`]. `position` extracts each row's `(key, id)`.
+    pub fn new<K: Serialize + DeserializeOwned, I: Serialize + DeserializeOwned>(
+        req: &HttpRequest,
+        limit: i64,
+        cursor: Option<&Cursor<K, I>>,
+        mut items: Vec<T>,
+        position: impl Fn(&T) -> (K, I),
+    ) -> Page<T> {
+        let has_more = items.len() as i64 > limit;
+        items.truncate(limit as usize);
+        let direction = cursor.map(|cursor| cursor.direction);
+        if direction == Some(Direction::Prev) {
+            items.reverse();
+        }
+
+        let link = |item: Option<&T>, direction: Direction| {
+            let (key, id) = position(item?);
+            let cursor = Cursor { key, id, direction };
+            Some(page_link(req, limit, &cursor.encode()))
+        };
+        let (more_after, more_before) = match direction {
+            None => (has_more, false),
+            Some(Direction::Next) => (has_more, true),
+            Some(Direction::Prev) => (true, has_more),
+        };
+        Page {
+            next: more_after
+                .then(|| link(items.last(), Direction::Next))
+                .flatten(),
+            prev: more_before
+                .then(|| link(items.first(), Direction::Prev))
+                .flatten(),
+            items,
+        }
+    }
+}
+
+/// The current URL with `limit` and `cursor` replaced, other parameters kept.
+fn page_link(req: &HttpRequest, limit: i64, cursor: &str) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    for (key, value) in url::form_urlencoded::parse(req.query_string().as_bytes()) {
+        if key != "limit" && key != "cursor" {
+            query.append_pair(&key, &value);
+        }
+    }
+    query.append_pair("limit", &limit.to_string());
+    query.append_pair("cursor", cursor);
+    format!("{}?{}", req.path(), query.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn cursor_from(link: &str) -> Cursor<i64> {
+        let value = link.split("cursor=").nth(1).expect("link has a cursor");
+        Cursor::decode(value).expect("link cursor decodes")
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            key: "2024-01-01".to_string(),
+            id: 7,
+            direction: Direction::Prev,
+        };
+        let decoded = Cursor::<String>::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.key, "2024-01-01");
+        assert_eq!(decoded.id, 7);
+        assert!(decoded.direction == Direction::Prev);
+    }
+
+    #[test]
+    fn cursor_rejects_garbage() {
+        assert!(Cursor::<i64>::decode("not a cursor").is_err());
+        let wrong_key = URL_SAFE_NO_PAD.encode(br#"{"k":"text","id":1,"d":"n"}"#);
+        assert!(Cursor::<i64>::decode(&wrong_key).is_err());
+    }
+
+    #[test]
+    fn sql_follows_direction() {
+        let (keyset, order) = Cursor::<i64>::sql(None, "k", "id", 2);
+        assert_eq!(
+            (keyset.as_str(), order.as_str()),
+            ("TRUE", "k DESC, id DESC")
+        );
+
+        let next = Cursor {
+            key: 1i64,
+            id: 1,
+            direction: Direction::Next,
+        };
+        let (keyset, order) = Cursor::sql(Some(&next), "k", "id", 2);
+        assert_eq!(
+            (keyset.as_str(), order.as_str()),
+            ("(k, id) < ($2, $3)", "k DESC, id DESC")
+        );
+
+        let prev = Cursor {
+            key: 1i64,
+            id: 1,
+            direction: Direction::Prev,
+        };
+        let (keyset, order) = Cursor::sql(Some(&prev), "k", "id", 3);
+        assert_eq!(
+            (keyset.as_str(), order.as_str()),
+            ("(k, id) > ($3, $4)", "k ASC, id ASC")
+        );
+    }
+
+    #[test]
+    fn first_page_links_forward_only() {
+        let req = TestRequest::with_uri("/api/posts?tag=rust").to_http_request();
+        let page = Page::new(&req, 2, None::<&Cursor<i64>>, vec![30, 20, 10], |&n| {
+            (n, n as i32)
+        });
+        assert_eq!(page.items, vec![30, 20]);
+        assert!(page.prev.is_none());
+
+        let next = page.next.expect("more items follow");
+        assert!(next.starts_with("/api/posts?tag=rust&limit=2&cursor="));
+        let cursor = cursor_from(&next);
+        assert_eq!((cursor.key, cursor.id), (20, 20));
+        assert!(cursor.direction == Direction::Next);
+    }
+
+    #[test]
+    fn last_page_links_back_only() {
+        let req = TestRequest::with_uri("/api/posts").to_http_request();
+        let after = Cursor {
+            key: 20i64,
+            id: 20,
+            direction: Direction::Next,
+        };
+        let page = Page::new(&req, 2, Some(&after), vec![10], |&n| (n, n as i32));
+        assert_eq!(page.items, vec![10]);
+        assert!(page.next.is_none());
+        let cursor = cursor_from(&page.prev.expect("earlier items exist"));
+        assert_eq!(cursor.key, 10);
+        assert!(cursor.direction == Direction::Prev);
+    }
+
+    #[test]
+    fn previous_pages_are_flipped_back() {
+        let req = TestRequest::with_uri("/api/posts").to_http_request();
+        let before = Cursor {
+            key: 30i64,
+            id: 30,
+            direction: Direction::Prev,
+        };
+        // Fetched ascending from the cursor, one extra to detect more.
+        let page = Page::new(&req, 2, Some(&before), vec![40, 50, 60], |&n| (n, n as i32));
+        assert_eq!(page.items, vec![50, 40]);
+        assert_eq!(cursor_from(&page.next.unwrap()).key, 40);
+        assert_eq!(cursor_from(&page.prev.unwrap()).key, 50);
+    }
+}
@@ -0,0 +1,31 @@
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long a signed preview link stays valid.
+pub const PREVIEW_TTL_HOURS: i64 = 72;
+
+fn mac(key: &[u8], post_id: i32, expires: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("preview:{post_id}:{expires}").as_bytes());
+    mac
+}
+
+/// Returns `(expires, signature)` for a preview link to an unpublished post.
+pub fn sign(key: &[u8], post_id: i32) -> (i64, String) {
+    let expires = (Utc::now() + Duration::hours(PREVIEW_TTL_HOURS)).timestamp();
+    let signature = hex::encode(mac(key, post_id, expires).finalize().into_bytes());
+    (expires, signature)
+}
+
+pub fn verify(key: &[u8], post_id: i32, expires: i64, signature: &str) -> bool {
+    if expires < Utc::now().timestamp() {
+        return false;
+    }
+    match hex::decode(signature) {
+        Ok(bytes) => mac(key, post_id, expires).verify_slice(&bytes).is_ok(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,377 @@
+use crate::error::ApiError;
+use actix_session::UserSession;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// In-memory buckets are swept once the map grows past this many keys.
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+/// A token bucket: `burst` requests at once, refilled at `per_second`.
+#[derive(Clone, Copy)]
+pub struct Policy {
+    pub name: &'static str,
+    pub burst: f64,
+    pub per_second: f64,
+}
+
+const COMMENTS: Policy = Policy {
+    name: "comments",
+    burst: 5.0,
+    per_second: 1.0 / 30.0,
+};
+const VIEWS: Policy = Policy {
+    name: "views",
+    burst: 10.0,
+    per_second: 1.0 / 10.0,
+};
+const SEARCH: Policy = Policy {
+    name: "search",
+    burst: 20.0,
+    per_second: 1.0,
+};
+const AUTH: Policy = Policy {
+    name: "auth",
+    burst: 10.0,
+    per_second: 1.0 / 6.0,
+};
+const API: Policy = Policy {
+    name: "api",
+    burst: 120.0,
+    per_second: 2.0,
+};
+
+/// Routes with their own budget. `*` matches a single path segment; anything
+/// else under `/api` falls back to [`API`], and pages and assets are unlimited.
+const ROUTES: &[(Method, &str, Policy)] = &[
+    (Method::POST, "/api/comments", COMMENTS),
+    (Method::PUT, "/api/comments/*", COMMENTS),
+    (Method::POST, "/api/comments/*/flag", COMMENTS),
+    (Method::POST, "/api/posts/*/comments", COMMENTS),
+    (Method::PUT, "/api/update_views/*", VIEWS),
+    (Method::GET, "/api/search", SEARCH),
+    (Method::GET, "/auth/start_github_oauth", AUTH),
+    (Method::GET, "/auth/github_oauth_redirect", AUTH),
+];
+
+fn route_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern = pattern.split('/');
+    let mut path = path.trim_end_matches('/').split('/');
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some("*"), Some(segment)) if !segment.is_empty() => {}
+            (Some(expected), Some(segment)) if expected == segment => {}
+            _ => return false,
+        }
+    }
+}
+
+fn policy_for(method: &Method, path: &str) -> Option<Policy> {
+    ROUTES
+        .iter()
+        .find(|(m, pattern, _)| m == method && route_matches(pattern, path))
+        .map(|(_, _, policy)| *policy)
+        .or_else(|| (path == "/api" || path.starts_with("/api/")).then_some(API))
+}
+
+/// A proxy address or CIDR block from `TRUSTED_PROXIES`.
+#[derive(Clone, Copy)]
+pub struct ProxyNet {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl ProxyNet {
+    pub fn parse(value: &str) -> Option<ProxyNet> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (value.parse().ok()?, None),
+        };
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(ProxyNet { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    policy: Policy,
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.policy.per_second).min(self.policy.burst)
+    }
+}
+
+/// Where bucket state lives. Postgres keeps limits consistent when more than
+/// one instance is serving traffic.
+pub enum Store {
+    Memory(Mutex<HashMap<String, Bucket>>),
+    Postgres(Pool<Postgres>),
+}
+
+impl Store {
+    pub fn memory() -> Store {
+        Store::Memory(Mutex::new(HashMap::new()))
+    }
+}
+
+pub struct RateLimiter {
+    store: Store,
+    trusted_proxies: Vec<ProxyNet>,
+}
+
+impl RateLimiter {
+    pub fn new(store: Store, trusted_proxies: Vec<ProxyNet>) -> RateLimiter {
+        RateLimiter {
+            store,
+            trusted_proxies,
+        }
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// The address of the client behind any trusted proxies. Forwarding
+    /// headers are only believed when the connection comes from one.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+        let peer = peer?.ip().to_canonical();
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        if let Some(ip) = header("Fly-Client-IP").and_then(|value| value.trim().parse().ok()) {
+            return Some(ip);
+        }
+        // Walk X-Forwarded-For from the nearest hop, skipping our own proxies.
+        let forwarded = header("X-Forwarded-For")
+            .unwrap_or_default()
+            .rsplit(',')
+            .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+            .find(|ip| !self.is_trusted(*ip));
+        Some(forwarded.unwrap_or(peer))
+    }
+
+    /// Takes a token for `key`. Returns how many seconds to wait when the
+    /// bucket is empty.
+    async fn acquire(&self, key: &str, policy: Policy) -> Result<Option<u64>, sqlx::Error> {
+        let (allowed, tokens) = match &self.store {
+            Store::Memory(buckets) => {
+                let mut buckets = buckets.lock().unwrap();
+                let now = Instant::now();
+                if buckets.len() > MAX_MEMORY_BUCKETS {
+                    // Buckets that have refilled are indistinguishable from new ones.
+                    buckets.retain(|_, bucket| bucket.refilled(now) < bucket.policy.burst);
+                }
+                let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+                    tokens: policy.burst,
+                    updated: now,
+                    policy,
+                });
+                bucket.tokens = bucket.refilled(now);
+                bucket.updated = now;
+                let allowed = bucket.tokens >= 1.0;
+                if allowed {
+                    bucket.tokens -= 1.0;
+                }
+                (allowed, bucket.tokens)
+            }
+            Store::Postgres(db) => {
+                sqlx::query_as::<_, (bool, f64)>(
+                    "INSERT INTO rate_limit_buckets AS b (key, tokens, allowed, updated_at) \
+                     VALUES ($1, $2 - 1, TRUE, now()) \
+                     ON CONFLICT (key) DO UPDATE SET \
+                         tokens = LEAST($2, b.tokens + EXTRACT(EPOCH FROM now() - b.updated_at)::float8 * $3) \
+                             - CASE WHEN LEAST($2, b.tokens + EXTRACT(EPOCH FROM now() - b.updated_at)::float8 * $3) >= 1 \
+                                    THEN 1 ELSE 0 END, \
+                         allowed = LEAST($2, b.tokens + EXTRACT(EPOCH FROM now() - b.updated_at)::float8 * $3) >= 1, \
+                         updated_at = now() \
+                     RETURNING b.allowed, b.tokens",
+                )
+                .bind(key)
+                .bind(policy.burst)
+                .bind(policy.per_second)
+                .fetch_one(db)
+                .await?
+            }
+        };
+
+        if allowed {
+            Ok(None)
+        } else {
+            Ok(Some(((1.0 - tokens) / policy.per_second).ceil().max(1.0) as u64))
+        }
+    }
+}
+
+/// Deletes Postgres buckets that have been idle long enough to be full again.
+#[tracing::instrument(skip_all)]
+pub async fn prune_buckets(db: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+    let result =
+        sqlx::query("DELETE FROM rate_limit_buckets WHERE updated_at < now() - interval '1 day'")
+            .execute(db)
+            .await?;
+    Ok(result.rows_affected())
+}
+
+/// Middleware applying the per-route [`Policy`] to each request. Signed-in
+/// users are limited per account, everyone else per client address.
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let policy = policy_for(req.method(), req.path());
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let (Some(policy), Some(limiter)) = (policy, limiter) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+
+    let identity = match req.get_session().get::<String>("user_id") {
+        Ok(Some(user_id)) => format!("user:{user_id}"),
+        _ => match limiter.client_ip(req.headers(), req.peer_addr()) {
+            Some(ip) => format!("ip:{ip}"),
+            None => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+        },
+    };
+    let key = format!("{}:{identity}", policy.name);
+
+    match limiter.acquire(&key, policy).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            let error = ApiError::RateLimited(retry_after);
+            return Ok(req.error_response(error).map_into_right_body());
+        }
+        Err(e) => {
+            // Fail open: a database hiccup shouldn't take the whole API down.
+            sentry::capture_error(&e);
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(value: &str) -> ProxyNet {
+        ProxyNet::parse(value).expect("valid proxy network")
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_blocks() {
+        let private = net("10.0.0.0/8");
+        assert!(private.contains(ip("10.1.2.3")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(net("192.168.1.7").contains(ip("192.168.1.7")));
+        assert!(!net("192.168.1.7").contains(ip("192.168.1.8")));
+        assert!(net("0.0.0.0/0").contains(ip("203.0.113.9")));
+        // IPv4-mapped addresses from a dual-stack listener still match.
+        assert!(private.contains(ip("::ffff:10.9.8.7")));
+        assert!(!private.contains(ip("fd00::1")));
+    }
+
+    #[test]
+    fn ipv6_blocks() {
+        let fly = net("fdaa::/16");
+        assert!(fly.contains(ip("fdaa:0:1::3")));
+        assert!(!fly.contains(ip("fdab::1")));
+        assert!(net("::1").contains(ip("::1")));
+        assert!(!net("::1").contains(ip("::2")));
+        assert!(net("::/0").contains(ip("2001:db8::1")));
+        assert!(!fly.contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn rejects_bad_networks() {
+        assert!(ProxyNet::parse("10.0.0.0/33").is_none());
+        assert!(ProxyNet::parse("::/129").is_none());
+        assert!(ProxyNet::parse("10.0.0.0/").is_none());
+        assert!(ProxyNet::parse("localhost").is_none());
+    }
+
+    #[test]
+    fn wildcards_match_one_segment() {
+        assert!(route_matches("/api/comments/*", "/api/comments/5"));
+        assert!(route_matches("/api/comments/*", "/api/comments/5/"));
+        assert!(!route_matches("/api/comments/*", "/api/comments"));
+        assert!(!route_matches("/api/comments/*", "/api/comments/"));
+        assert!(!route_matches("/api/comments/*", "/api/comments/5/flag"));
+        assert!(route_matches(
+            "/api/comments/*/flag",
+            "/api/comments/5/flag"
+        ));
+        assert!(!route_matches(
+            "/api/comments/*/flag",
+            "/api/comments//flag"
+        ));
+    }
+
+    #[test]
+    fn routes_pick_their_policy() {
+        let name = |method, path| policy_for(&method, path).map(|p| p.name);
+        assert_eq!(name(Method::POST, "/api/comments"), Some("comments"));
+        assert_eq!(name(Method::GET, "/api/comments"), Some("api"));
+        assert_eq!(name(Method::GET, "/auth/start_github_oauth"), Some("auth"));
+        assert_eq!(name(Method::GET, "/api"), Some("api"));
+        assert_eq!(name(Method::GET, "/apiary"), None);
+        assert_eq!(name(Method::GET, "/blog/1"), None);
+    }
+
+    #[test]
+    fn forwarded_addresses_need_a_trusted_peer() {
+        let limiter = RateLimiter::new(Store::memory(), vec![net("172.16.0.0/12")]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            actix_web::http::header::HeaderName::from_static("fly-client-ip"),
+            "203.0.113.7".parse().unwrap(),
+        );
+        let proxy = Some("172.16.3.4:5000".parse().unwrap());
+        let direct = Some("198.51.100.2:5000".parse().unwrap());
+        assert_eq!(limiter.client_ip(&headers, proxy), Some(ip("203.0.113.7")));
+        assert_eq!(
+            limiter.client_ip(&headers, direct),
+            Some(ip("198.51.100.2"))
+        );
+
+        let untrusting = RateLimiter::new(Store::memory(), Vec::new());
+        assert_eq!(
+            untrusting.client_ip(&headers, proxy),
+            Some(ip("172.16.3.4"))
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::error::ApiError;
+use crate::markdown::{escape_html, plain_text};
+use crate::moderation::VISIBLE_COMMENT_FILTER;
+use crate::services::PUBLISHED_FILTER;
+use crate::state::AppState;
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+/// Deeper pages are clamped; nobody reads that far and the offset stays sane.
+const MAX_PAGE: i64 = 1000;
+const MAX_TERMS: usize = 8;
+
+// Control characters cannot appear in posts or comments, so they make safe
+// highlight markers that survive HTML escaping of the snippet.
+const START_MARK: &str = "\u{2}";
+const STOP_MARK: &str = "\u{3}";
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchScope {
+    #[default]
+    All,
+    Posts,
+    Comments,
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    #[serde(default, rename = "type")]
+    pub scope: SearchScope,
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(FromRow)]
+struct SearchRow {
+    kind: String,
+    id: i32,
+    slug: Option<String>,
+    title: String,
+    snippet: String,
+    rank: f32,
+    date: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    kind: String,
+    id: i32,
+    slug: Option<String>,
+    title: String,
+    snippet: String,
+    rank: f32,
+    date: DateTime<Utc>,
+}
+
+/// Turns free text into a prefix-matching tsquery (`rust:* & actix:*`). Only
+/// alphanumeric runs survive, so user input can never inject tsquery operators.
+fn to_prefix_query(input: &str) -> Option<String> {
+    let terms: Vec<String> = input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .take(MAX_TERMS)
+        .map(|term| format!("{}:*", term.to_lowercase()))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" & "))
+    }
+}
+
+fn highlight(snippet: &str, kind: &str) -> String {
+    // Comments are stored as sanitized HTML; posts are indexed as plain text.
+    let text = if kind == "comment" {
+        plain_text(snippet)
+    } else {
+        snippet.to_string()
+    };
+    escape_html(&text)
+        .replace(START_MARK, "<mark>")
+        .replace(STOP_MARK, "</mark>")
+}
+
+#[get("/search")]
+pub async fn search_content(
+    query: web::Query<SearchQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let tsquery = to_prefix_query(&query.q)
+        .ok_or_else(|| ApiError::invalid("Search query must contain a word"))?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let page = query.page.unwrap_or(1).clamp(1, MAX_PAGE);
+    let include_posts = query.scope != SearchScope::Comments;
+    let include_comments = query.scope != SearchScope::Posts;
+
+    // Ranking and paging happen before ts_headline so only the returned page
+    // pays for snippet generation.
+    let mut rows = sqlx::query_as::<_, SearchRow>(&format!(
+        "WITH q AS (SELECT to_tsquery('english', $1) AS query), \
+         matches AS ( \
+             SELECT 'post' AS kind, p.id, p.slug, p.title, r.plain_text AS body, \
+                    ts_rank(p.search_vector, q.query) AS rank, \
+                    p.published_date::timestamptz AS date \
+             FROM posts p CROSS JOIN q \
+             JOIN LATERAL (SELECT plain_text FROM post_revisions \
+                           WHERE post_id = p.id ORDER BY id DESC LIMIT 1) r ON true \
+             WHERE $2 AND {PUBLISHED_FILTER} \
+               AND p.search_vector @@ q.query \
+             UNION ALL \
+             SELECT 'comment', c.id, NULL, COALESCE(c.name, ''), COALESCE(c.comment, ''), \
+                    ts_rank(c.search_vector, q.query), c.timestamp \
+             FROM comments c CROSS JOIN q \
+             WHERE $3 AND c.deleted_at IS NULL AND {VISIBLE_COMMENT_FILTER} \
+               AND c.search_vector @@ q.query \
+             ORDER BY rank DESC, date DESC \
+             LIMIT $4 OFFSET $5) \
+         SELECT m.kind, m.id, m.slug, m.title, \
+                ts_headline('english', m.body, q.query, \
+                    'StartSel={START_MARK}, StopSel={STOP_MARK}, MaxFragments=2, MaxWords=30, MinWords=10') AS snippet, \
+                m.rank, m.date \
+         FROM matches m CROSS JOIN q ORDER BY m.rank DESC, m.date DESC"
+    ))
+    .bind(&tsquery)
+    .bind(include_posts)
+    .bind(include_comments)
+    .bind(limit + 1)
+    .bind((page - 1) * limit)
+    .fetch_all(&data.db)
+    .await?;
+
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+    let results: Vec<SearchResult> = rows
+        .into_iter()
+        .map(|row| SearchResult {
+            snippet: highlight(&row.snippet, &row.kind),
+            kind: row.kind,
+            id: row.id,
+            slug: row.slug,
+            title: row.title,
+            rank: row.rank,
+            date: row.date,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "query": query.q,
+        "page": page,
+        "limit": limit,
+        "has_more": has_more,
+        "results": results
+    })))
+}
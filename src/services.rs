@@ -1,380 +1,917 @@
+use crate::error::ApiError;
+use crate::markdown;
+use crate::pagination::{Cursor, Page, PageQuery};
+use crate::preview;
+use crate::rate_limit::RateLimiter;
+use crate::slugs;
 use crate::state::AppState;
+use crate::taxonomy::{self, SeriesAssignment};
+use crate::users::{AuthenticatedUser, Permission};
+use crate::views;
 use actix_session::Session;
-use actix_web::{get, post, put, delete, web, HttpResponse, Responder};
+use actix_web::http::header::USER_AGENT;
+use actix_web::{get, post, put, delete, web, HttpRequest, HttpResponse};
 use chrono::{NaiveDate, DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{self, FromRow, Row};
-use std::env;
+use std::time::Instant;
+use tracing::Instrument;
 
 #[derive(Deserialize)]
 struct Repo {
     stargazers_count: i32,
 }
 
-#[derive(Deserialize)]
-pub struct Info {
-    id: String,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum PostStatus {
+    Draft,
+    Scheduled,
+    Published,
+    Unlisted,
+    Archived,
+}
+
+impl PostStatus {
+    /// Whether anyone holding the link may read the post. Only published posts
+    /// are listed; unlisted and archived ones are reachable by URL alone.
+    fn is_public(self) -> bool {
+        matches!(
+            self,
+            PostStatus::Published | PostStatus::Unlisted | PostStatus::Archived
+        )
+    }
 }
 
+/// Columns selected into [`Post`] from `posts p`.
+pub(crate) const POST_COLUMNS: &str = "p.id, p.slug, p.title, p.published_date, p.views, \
+     ARRAY(SELECT t.slug FROM post_tags pt JOIN tags t ON t.id = pt.tag_id \
+           WHERE pt.post_id = p.id ORDER BY t.slug) AS tags, \
+     (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id AND c.deleted_at IS NULL \
+      AND c.status IN ('approved', 'flagged')) AS comment_count";
+
+/// Posts visible in public listings, feeds and search, with `posts` aliased as `p`.
+pub(crate) const PUBLISHED_FILTER: &str =
+    "p.status = 'published' AND p.published_date <= CURRENT_DATE";
+
 #[derive(Serialize, FromRow)]
-struct Post {
-    id: i32,
+pub(crate) struct Post {
+    pub(crate) id: i32,
+    slug: String,
     title: String,
-    published_date: NaiveDate,
+    pub(crate) published_date: NaiveDate,
     views: i32,
+    tags: Vec<String>,
+    comment_count: i64,
 }
 
 #[derive(Serialize, FromRow)]
-struct Comment {
+struct AdminPost {
     id: i32,
-    #[serde(skip_serializing)]
-    userid: String,
-    name: String,
-    comment: String,
-    #[serde(skip_serializing)]
-    timestamp: DateTime<Utc>,
+    slug: String,
+    title: String,
+    published_date: NaiveDate,
+    views: i32,
+    status: PostStatus,
+    publish_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Deserialize)]
-pub struct CreateComment {
-    pub comment: String,
+pub struct PreviewQuery {
+    pub expires: Option<i64>,
+    pub signature: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct CreatePost {
     pub title: String,
+    /// Derived from the title when omitted.
+    pub slug: Option<String>,
     pub content: String,
     pub published_date: NaiveDate,
+    pub status: Option<PostStatus>,
+    pub publish_at: Option<DateTime<Utc>>,
+    pub tags: Option<Vec<String>>,
+    pub categories: Option<Vec<String>>,
+    pub series: Option<SeriesAssignment>,
 }
 
 #[derive(Deserialize)]
 pub struct UpdatePost {
     pub title: Option<String>,
+    /// Renaming keeps the previous slug as a permanent redirect.
+    pub slug: Option<String>,
     pub content: Option<String>,
     pub published_date: Option<NaiveDate>,
+    pub status: Option<PostStatus>,
+    pub publish_at: Option<DateTime<Utc>>,
+    pub tags: Option<Vec<String>>,
+    pub categories: Option<Vec<String>>,
+    /// `null` removes the post from its series; omitting the field leaves it alone.
+    #[serde(default, deserialize_with = "taxonomy::deserialize_some")]
+    pub series: Option<Option<SeriesAssignment>>,
 }
 
 
+/// How long a star count is served from the cache before it is refetched.
+pub const STAR_CACHE_MINUTES: i64 = 15;
+
+/// Fetches the repository's star count from GitHub into the cache. Failures
+/// are recorded on the cache too, for the admin health report.
+pub async fn refresh_stars(data: &AppState) -> Result<i32, ApiError> {
+    let client = reqwest::Client::new();
+    let owner = &data.config.github.owner;
+    let repo = &data.config.github.repo;
+
+    let request_url = format!("https://api.github.com/repos/{owner}/{repo}");
+
+    let started = Instant::now();
+    let result = async {
+        client
+            .get(&request_url)
+            .header(reqwest::header::USER_AGENT, "rayspace.dev")
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Repo>()
+            .await
+    }
+    .instrument(tracing::info_span!("github.repo", otel.kind = "client", %owner, %repo))
+    .await;
+    data.metrics.github_call("stars", started.elapsed(), result.is_ok());
+
+    let mut cache = data.star_cache.write().expect("Failed to acquire write lock");
+    cache.last_attempt = Some(Utc::now());
+    let repo = match result {
+        Ok(repo) => repo,
+        Err(e) => {
+            cache.last_error = Some(e.to_string());
+            return Err(e.into());
+        }
+    };
+    cache.star_count = repo.stargazers_count;
+    cache.last_fetched = Utc::now();
+    cache.last_error = None;
+    Ok(repo.stargazers_count)
+}
+
+/// The cached star count. A background job keeps it fresh; this only goes to
+/// GitHub itself if that job has fallen behind.
 #[get("/github_stars")]
-pub async fn fetch_stars(data: web::Data<AppState>) -> impl Responder {
+pub async fn fetch_stars(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
     let (should_fetch, current_stars) = {
         let cache = data.star_cache.read().expect("Failed to acquire read lock");
         let should_fetch = Utc::now()
             .signed_duration_since(cache.last_fetched)
-            .num_minutes() >= 15;
+            .num_minutes() >= STAR_CACHE_MINUTES;
         (should_fetch, cache.star_count)
     };
 
+    data.metrics.star_cache(!should_fetch);
     if !should_fetch {
-        return HttpResponse::Ok().json(serde_json::json!({ "stars": current_stars }));
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "stars": current_stars })));
     }
 
-    let client = reqwest::Client::new();
-    let owner = env::var("GITHUB_OWNER").unwrap_or_else(|_| String::from("rx0a"));
-    let repo = env::var("GITHUB_REPO").unwrap_or_else(|_| String::from("rayspace.dev"));
-
-    let request_url = format!("https://api.github.com/repos/{owner}/{repo}");
-
-    match client
-        .get(&request_url)
-        .header(reqwest::header::USER_AGENT, "rayspace.dev")
-        .send()
-        .await
-    {
-        Ok(response) => match response.json::<Repo>().await {
-            Ok(repo) => {
-                let mut cache = data.star_cache.write().expect("Failed to acquire write lock");
-                cache.star_count = repo.stargazers_count;
-                cache.last_fetched = Utc::now();
-
-                HttpResponse::Ok().json(serde_json::json!({ "stars": repo.stargazers_count }))
-            }
-            Err(e) => {
-                sentry::capture_error(&e);
-                HttpResponse::InternalServerError().json("An error occurred")
-            }
-        },
-        Err(e) => {
-            sentry::capture_error(&e);
-            HttpResponse::InternalServerError().json("An error occurred")
-        },
-    }
+    let stars = refresh_stars(&data).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "stars": stars })))
 }
 
-#[put("/update_views/{id}")]
-pub async fn update_views(info: web::Path<Info>, data: web::Data<AppState>) -> impl Responder {
-    let id: i32 = info.id.parse().unwrap_or_default();
-
-    match sqlx::query("UPDATE posts SET views = views + 1 WHERE id = $1")
-        .bind(id)
-        .execute(&data.db)
-        .await
-    {
-        Ok(_) => HttpResponse::Ok().json("Updated views"),
-        Err(_) => {
-            HttpResponse::InternalServerError().json("An error occurred")
-        }
+/// Counts a view of a public post. Bots and repeat views within the dedup
+/// window are acknowledged but not counted.
+#[put("/update_views/{key}")]
+pub async fn update_views(
+    req: HttpRequest,
+    session: Session,
+    path: web::Path<String>,
+    limiter: web::Data<RateLimiter>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let post = slugs::public_post(&data.db, &path.into_inner()).await?;
+
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|agent| agent.to_str().ok());
+    if views::is_bot(user_agent) {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "counted": false })));
     }
+    let visitor = match session.get::<String>("user_id") {
+        Ok(Some(user_id)) => format!("user:{user_id}"),
+        _ => match limiter.client_ip(req.headers(), req.peer_addr()) {
+            Some(ip) => format!("ip:{ip}|{}", user_agent.unwrap_or_default()),
+            None => return Ok(HttpResponse::Ok().json(serde_json::json!({ "counted": false }))),
+        },
+    };
+
+    let counted = views::record_view(&data.db, post.id, &visitor).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "counted": counted })))
 }
 
 #[get("/user_status")]
-pub async fn user_status(session: Session) -> impl Responder {
-    let user_id = session.get::<String>("user_id");
-    let user_name = session.get::<String>("user_name");
-
-    if let (Ok(Some(_)), Ok(Some(_))) = (user_id, user_name) {
-        HttpResponse::Ok().json(serde_json::json!({
-            "authenticated": true
-        }))
-    } else {
-        HttpResponse::Ok().json(serde_json::json!({
+pub async fn user_status(user: Option<AuthenticatedUser>) -> HttpResponse {
+    match user {
+        Some(user) => HttpResponse::Ok().json(serde_json::json!({
+            "authenticated": true,
+            "roles": user.role_names()
+        })),
+        None => HttpResponse::Ok().json(serde_json::json!({
             "authenticated": false
-        }))
+        })),
     }
 }
 
 #[get("/posts")]
-pub async fn fetch_posts(state: web::Data<AppState>) -> impl Responder {
-    match sqlx::query_as::<_, Post>("SELECT id, title, published_date, views FROM posts")
-        .fetch_all(&state.db)
-        .await
-    {
-        Ok(posts) => {
-            if posts.is_empty() {
-                HttpResponse::NotFound().json("No posts found")
-            } else {
-                HttpResponse::Ok().json(posts)
-            }
-        }
-        Err(e) => {
-            sentry::capture_error(&e);
-            HttpResponse::InternalServerError().json("An error occurred")
-        }
+pub async fn fetch_posts(
+    req: HttpRequest,
+    query: web::Query<PageQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let cursor = query.cursor::<NaiveDate, i32>()?;
+    let limit = query.limit();
+    let (keyset, order) = Cursor::sql(cursor.as_ref(), "p.published_date", "p.id", 2);
+
+    let sql = format!(
+        "SELECT {POST_COLUMNS} FROM posts p WHERE {PUBLISHED_FILTER} AND {keyset} \
+         ORDER BY {order} LIMIT $1"
+    );
+    let mut posts = sqlx::query_as::<_, Post>(&sql).bind(limit + 1);
+    if let Some(cursor) = &cursor {
+        posts = posts.bind(cursor.key).bind(cursor.id);
     }
+
+    let posts = posts.fetch_all(&state.db).await?;
+    Ok(HttpResponse::Ok().json(Page::new(&req, limit, cursor.as_ref(), posts, |post| {
+        (post.published_date, post.id)
+    })))
 }
 
-#[get("/comments")]
-pub async fn fetch_comments(state: web::Data<AppState>) -> impl Responder {
-    match sqlx::query_as::<_, Comment>("SELECT id, userid, name, comment, timestamp FROM comments ORDER BY timestamp DESC LIMIT 100")
-        .fetch_all(&state.db)
-        .await
-    {
-        Ok(comments) => {
-            if comments.is_empty() {
-                HttpResponse::NotFound().json("No comments found")
-            } else {
-                HttpResponse::Ok().json(comments)
+/// Metadata for a single post by slug or id. Old slugs redirect to the current one.
+#[get("/posts/{key}")]
+pub async fn fetch_post(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let key = path.into_inner();
+
+    let resolved = slugs::public_post(&data.db, &key).await?;
+    if resolved.slug != key {
+        return Ok(slugs::permanent_redirect(&req, &format!("/api/posts/{}", resolved.slug)));
+    }
+
+    let post = sqlx::query_as::<_, Post>(&format!("SELECT {POST_COLUMNS} FROM posts p WHERE p.id = $1"))
+        .bind(resolved.id)
+        .fetch_one(&data.db)
+        .await?;
+    Ok(HttpResponse::Ok().json(post))
+}
+
+#[derive(Serialize, FromRow)]
+struct PostRevision {
+    id: i32,
+    post_id: i32,
+    author_id: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+fn render_post_html(title: &str, toc: &str, rendered: &str) -> String {
+    format!(
+        "<div class='post-container'><h1 class='post-title'>{}</h1>{}<div class='post-content'>{}</div></div>",
+        markdown::escape_html(title),
+        toc,
+        rendered
+    )
+}
+
+#[tracing::instrument(skip_all, fields(post_id = post_id))]
+async fn insert_revision(
+    executor: impl sqlx::PgExecutor<'_>,
+    post_id: i32,
+    content: &str,
+    author_id: &Option<String>,
+) -> Result<i32, sqlx::Error> {
+    let rendered = markdown::render(content);
+    sqlx::query_scalar(
+        "INSERT INTO post_revisions (post_id, content, rendered, toc, plain_text, author_id) \
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+    )
+    .bind(post_id)
+    .bind(content)
+    .bind(&rendered.html)
+    .bind(&rendered.toc)
+    .bind(&rendered.text)
+    .bind(author_id)
+    .fetch_one(executor)
+    .await
+}
+
+/// The body of a post file written before posts lived in the database: the
+/// inside of its `post-content` div, or the whole file if it has none.
+fn legacy_post_body(html: &str) -> &str {
+    let start = html
+        .find("class='post-content'")
+        .or_else(|| html.find("class=\"post-content\""));
+    let Some(start) = start else {
+        return html.trim();
+    };
+    let Some(open_end) = html[start..].find('>').map(|end| start + end + 1) else {
+        return html.trim();
+    };
+    // The content div and the container close at the very end of the file.
+    let body = html[open_end..].trim_end();
+    let body = body.strip_suffix("</div>").unwrap_or(body).trim_end();
+    body.strip_suffix("</div>").unwrap_or(body)
+}
+
+/// Markdown source for a legacy post: its HTML kept as a single raw HTML block,
+/// after a comment recording where it came from. A blank line would end the
+/// block and leave indented markup to be read as a code block, so blank lines
+/// become empty comments, which the sanitizer removes from the output.
+fn legacy_post_source(path: &str, body: &str) -> String {
+    let mut source = format!("<!-- Imported as HTML from {path} -->\n<div class=\"legacy-post\">\n");
+    for line in body.lines() {
+        let line = if line.trim().is_empty() { "<!-- -->" } else { line };
+        source.push_str(line);
+        source.push('\n');
+    }
+    source.push_str("</div>\n");
+    source
+}
+
+/// Imports `{id}.html` files from `dir`, where post bodies were kept before
+/// they moved into `post_revisions`, as the first revision of each post that
+/// has none yet. Runs on every boot and does nothing once they are imported.
+#[tracing::instrument(skip(db))]
+pub async fn import_legacy_posts(db: &sqlx::Pool<sqlx::Postgres>, dir: &str) -> Result<u64, sqlx::Error> {
+    let missing: Vec<i32> = sqlx::query_scalar(
+        "SELECT id FROM posts p WHERE NOT EXISTS (SELECT 1 FROM post_revisions WHERE post_id = p.id)",
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut imported = 0;
+    for post_id in missing {
+        let path = format!("{dir}/{post_id}.html");
+        let html = match std::fs::read_to_string(&path) {
+            Ok(html) => html,
+            Err(e) => {
+                tracing::warn!(post_id, error = %e, "No body to import for post");
+                continue;
             }
-        }
-        Err(_) => {
-            HttpResponse::InternalServerError().json("An error occurred")
-        }
+        };
+        let mut tx = db.begin().await?;
+        let source = legacy_post_source(&path, legacy_post_body(&html));
+        insert_revision(&mut *tx, post_id, &source, &None).await?;
+        refresh_search_vector(&mut *tx, post_id).await?;
+        tx.commit().await?;
+        imported += 1;
     }
+    if imported > 0 {
+        tracing::info!(imported, "Imported legacy post bodies");
+    }
+    Ok(imported)
 }
 
-#[post("/comments")]
-pub async fn create_comment(
-    session: Session,
-    comment_body: web::Json<CreateComment>,
+/// Rebuilds `posts.search_vector` from the title and latest revision. Called in
+/// the same transaction as any write that changes either.
+#[tracing::instrument(skip_all, fields(post_id = post_id))]
+async fn refresh_search_vector(
+    executor: impl sqlx::PgExecutor<'_>,
+    post_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE posts p SET search_vector = \
+         setweight(to_tsvector('english', p.title), 'A') || \
+         setweight(to_tsvector('english', COALESCE( \
+             (SELECT plain_text FROM post_revisions WHERE post_id = p.id ORDER BY id DESC LIMIT 1), '')), 'B') \
+         WHERE p.id = $1",
+    )
+    .bind(post_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(post_id = post_id))]
+async fn latest_revision_content(
+    executor: impl sqlx::PgExecutor<'_>,
+    post_id: i32,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT content FROM post_revisions WHERE post_id = $1 ORDER BY id DESC LIMIT 1",
+    )
+    .bind(post_id)
+    .fetch_optional(executor)
+    .await
+}
+
+#[get("/posts/{key}.html")]
+pub async fn serve_post(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<PreviewQuery>,
+    user: Option<AuthenticatedUser>,
     data: web::Data<AppState>,
-) -> impl Responder {
-    const MAX_CHARS: usize = 255;
-
-    let user_id = session.get::<String>("user_id");
-    let user_name = session.get::<String>("user_name");
-
-    if let (Ok(Some(user_id)), Ok(Some(user_name))) = (user_id, user_name) {
-        if user_id.len() > MAX_CHARS
-            || user_name.len() > MAX_CHARS
-            || comment_body.comment.len() > MAX_CHARS
-        {
-            return HttpResponse::BadRequest().body("Input exceeds maximum allowed characters");
+) -> Result<HttpResponse, ApiError> {
+    let key = path.into_inner();
+
+    let resolved = slugs::resolve_post(&data.db, &key)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Post not found"))?;
+    let post_id = resolved.id;
+
+    let row = sqlx::query(
+        "SELECT p.title, p.status, r.toc, r.rendered FROM posts p \
+         JOIN post_revisions r ON r.post_id = p.id \
+         WHERE p.id = $1 ORDER BY r.id DESC LIMIT 1",
+    )
+    .bind(post_id)
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("Post not found"))?;
+
+    let status: PostStatus = row.get(1);
+    let has_preview = match (query.expires, &query.signature) {
+        (Some(expires), Some(signature)) => {
+            preview::verify(&data.config.secret_key, post_id, expires, signature)
         }
-        let sanitized_comment = ammonia::clean(&comment_body.comment);
+        _ => false,
+    };
+    let is_editor = user.is_some_and(|user| user.can(Permission::ManagePosts));
 
-        match sqlx::query(
-            "INSERT INTO comments (userid, name, comment) VALUES ($1, $2, $3) RETURNING id, userid, name, comment, timestamp",
-        )
-        .bind(&user_id)
-        .bind(&user_name)
-        .bind(&sanitized_comment)
-        .fetch_one(&data.db)
-        .await
-        {
-            Ok(row) => {
-                let id: Result<i32, _> = row.try_get(0);
-                let userid: Result<String, _> = row.try_get(1);
-                let name: Result<String, _> = row.try_get(2);
-                let comment: Result<String, _> = row.try_get(3);
-                let timestamp: Result<DateTime<Utc>, _> = row.try_get(4);
-
-                match (id, userid, name, comment, timestamp) {
-                    (Ok(id), Ok(userid), Ok(name), Ok(comment), Ok(timestamp)) => {
-                        let comment = Comment {
-                            id,
-                            userid,
-                            name,
-                            comment,
-                            timestamp,
-                        };
-                        HttpResponse::Ok().json(comment)
-                    }
-                    _ => HttpResponse::InternalServerError().body("Failed to parse comment fields"),
-                }
-            },
-            Err(_) => HttpResponse::InternalServerError().body("Failed to create comment"),
-        }
-    } else {
-        HttpResponse::Unauthorized().body("User must be logged in to create a comment")
+    // Hidden posts 404 rather than 403 so drafts are not discoverable by id.
+    if !status.is_public() && !has_preview && !is_editor {
+        return Err(ApiError::not_found("Post not found"));
+    }
+    // Checked after visibility so an old slug never reveals a hidden post's new one.
+    if resolved.slug != key {
+        return Ok(slugs::permanent_redirect(&req, &format!("/posts/{}.html", resolved.slug)));
+    }
+
+    let title: String = row.get(0);
+    let toc: String = row.get(2);
+    let rendered: String = row.get(3);
+    let mut response = HttpResponse::Ok();
+    response.content_type("text/html; charset=utf-8");
+    if !status.is_public() {
+        response.insert_header(("X-Robots-Tag", "noindex"));
+        response.insert_header(("Cache-Control", "private, no-store"));
     }
+    Ok(response.body(render_post_html(&title, &toc, &rendered)))
 }
 
-fn is_admin(session: &Session) -> bool {
-    if let Ok(Some(user_id)) = session.get::<String>("user_id") {
-        user_id == "156246723"
-    } else {
-        false
+/// Checks a post's schedule and returns the `publish_at` to store. It is only
+/// kept while the post is scheduled, so leaving that status clears it.
+fn schedule(
+    status: PostStatus,
+    publish_at: Option<DateTime<Utc>>,
+) -> Result<Option<DateTime<Utc>>, ApiError> {
+    match (status, publish_at) {
+        (PostStatus::Scheduled, None) => Err(ApiError::invalid("Scheduled posts require publish_at")),
+        (PostStatus::Scheduled, publish_at) => Ok(publish_at),
+        _ => Ok(None),
     }
 }
 
+#[get("/admin/posts")]
+pub async fn fetch_admin_posts(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+    let cursor = query.cursor::<NaiveDate, i32>()?;
+    let limit = query.limit();
+    let (keyset, order) = Cursor::sql(cursor.as_ref(), "published_date", "id", 2);
+
+    let sql = format!(
+        "SELECT id, slug, title, published_date, views, status, publish_at FROM posts \
+         WHERE {keyset} ORDER BY {order} LIMIT $1"
+    );
+    let mut posts = sqlx::query_as::<_, AdminPost>(&sql).bind(limit + 1);
+    if let Some(cursor) = &cursor {
+        posts = posts.bind(cursor.key).bind(cursor.id);
+    }
+
+    let posts = posts.fetch_all(&data.db).await?;
+    Ok(HttpResponse::Ok().json(Page::new(&req, limit, cursor.as_ref(), posts, |post| {
+        (post.published_date, post.id)
+    })))
+}
+
+#[post("/admin/posts/{id}/preview")]
+pub async fn create_preview_link(
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+
+    let post_id = path.into_inner();
+
+    let slug = sqlx::query_scalar::<_, String>("SELECT slug FROM posts WHERE id = $1")
+        .bind(post_id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Post not found"))?;
+    let (expires, signature) = preview::sign(&data.config.secret_key, post_id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "url": format!("/posts/{slug}.html?expires={expires}&signature={signature}"),
+        "expires": expires
+    })))
+}
+
+const SLUG_IN_USE: &str = "Slug already in use";
+
 #[post("/admin/posts")]
 pub async fn create_post(
-    session: Session,
+    user: AuthenticatedUser,
     post_data: web::Json<CreatePost>,
     data: web::Data<AppState>,
-) -> impl Responder {
-    if !is_admin(&session) {
-        return HttpResponse::Unauthorized().json("Admin access required");
-    }
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+
+    let status = post_data.status.unwrap_or(PostStatus::Published);
+    let publish_at = schedule(status, post_data.publish_at)?;
+    taxonomy::validate_post_terms(post_data.tags.as_ref(), post_data.categories.as_ref())
+        .map_err(ApiError::invalid)?;
+    let requested_slug = post_data
+        .slug
+        .as_deref()
+        .map(slugs::normalize_slug)
+        .transpose()
+        .map_err(ApiError::invalid)?;
+
+    let author_id = Some(user.id.clone());
+
+    let mut tx = data.db.begin().await?;
+
+    let slug = match requested_slug {
+        Some(slug) if !slugs::slug_available(&mut tx, &slug, None).await? => {
+            return Err(ApiError::conflict(SLUG_IN_USE));
+        }
+        Some(slug) => slug,
+        None => slugs::generate_slug(&mut tx, &post_data.title).await?,
+    };
 
-    match sqlx::query("INSERT INTO posts (title, published_date, views) VALUES ($1, $2, 0) RETURNING id")
-        .bind(&post_data.title)
-        .bind(&post_data.published_date)
-        .fetch_one(&data.db)
-        .await
-    {
-        Ok(row) => {
-            let post_id: i32 = row.get(0);
-            
-            let html_content = format!(
-                "<div class='post-container'><h1 class='post-title'>{}</h1><div class='post-content'>{}</div></div>",
-                post_data.title, post_data.content
-            );
-            
-            if std::fs::write(format!("./assets/posts/{}.html", post_id), html_content).is_err() {
-                return HttpResponse::InternalServerError().json("Failed to create post file");
-            }
-            
-            HttpResponse::Ok().json(serde_json::json!({ "id": post_id, "message": "Post created successfully" }))
+    // A concurrent request can still claim the slug after the check above.
+    let post_id: i32 = sqlx::query_scalar(
+        "INSERT INTO posts (title, slug, published_date, views, status, publish_at) \
+         VALUES ($1, $2, $3, 0, $4, $5) RETURNING id",
+    )
+    .bind(&post_data.title)
+    .bind(&slug)
+    .bind(post_data.published_date)
+    .bind(status)
+    .bind(publish_at)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(ApiError::on_unique(SLUG_IN_USE))?;
+
+    insert_revision(&mut *tx, post_id, &post_data.content, &author_id).await?;
+    refresh_search_vector(&mut *tx, post_id).await?;
+
+    if let Some(tags) = &post_data.tags {
+        taxonomy::set_post_tags(&mut tx, post_id, tags).await?;
+    }
+    if let Some(categories) = &post_data.categories {
+        taxonomy::set_post_categories(&mut tx, post_id, categories).await?;
+    }
+    if let Some(series) = &post_data.series {
+        if !taxonomy::set_post_series(&mut tx, post_id, Some(series)).await? {
+            return Err(ApiError::invalid("Series not found"));
         }
-        Err(_) => HttpResponse::InternalServerError().json("Failed to create post")
     }
+
+    tx.commit().await?;
+    data.metrics.post_created();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": post_id,
+        "slug": slug,
+        "message": "Post created successfully"
+    })))
 }
 
 #[put("/admin/posts/{id}")]
 pub async fn update_post(
-    session: Session,
+    user: AuthenticatedUser,
     path: web::Path<i32>,
     post_data: web::Json<UpdatePost>,
     data: web::Data<AppState>,
-) -> impl Responder {
-    if !is_admin(&session) {
-        return HttpResponse::Unauthorized().json("Admin access required");
-    }
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+
+    taxonomy::validate_post_terms(post_data.tags.as_ref(), post_data.categories.as_ref())
+        .map_err(ApiError::invalid)?;
+
+    let requested_slug = post_data
+        .slug
+        .as_deref()
+        .map(slugs::normalize_slug)
+        .transpose()
+        .map_err(ApiError::invalid)?;
 
     let post_id = path.into_inner();
-    
-    if let Some(title) = &post_data.title {
-        if sqlx::query("UPDATE posts SET title = $1 WHERE id = $2")
-            .bind(title)
+    let author_id = Some(user.id.clone());
+
+    let mut tx = data.db.begin().await?;
+
+    let (stored_status, stored_publish_at): (PostStatus, Option<DateTime<Utc>>) =
+        sqlx::query_as("SELECT status, publish_at FROM posts WHERE id = $1 FOR UPDATE")
             .bind(post_id)
-            .execute(&data.db)
-            .await
-            .is_err()
-        {
-            return HttpResponse::InternalServerError().json("Failed to update post title");
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| ApiError::not_found("Post not found"))?;
+    // Fields missing from the request keep their stored values.
+    let status = post_data.status.unwrap_or(stored_status);
+    let publish_at = schedule(status, post_data.publish_at.or(stored_publish_at))?;
+
+    sqlx::query(
+        "UPDATE posts SET title = COALESCE($1, title), published_date = COALESCE($2, published_date), \
+         status = $3, publish_at = $4 WHERE id = $5",
+    )
+    .bind(&post_data.title)
+    .bind(post_data.published_date)
+    .bind(status)
+    .bind(publish_at)
+    .bind(post_id)
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(slug) = &requested_slug {
+        if !slugs::slug_available(&mut tx, slug, Some(post_id)).await? {
+            return Err(ApiError::conflict(SLUG_IN_USE));
         }
-    }
-    
-    if let Some(date) = &post_data.published_date {
-        if sqlx::query("UPDATE posts SET published_date = $1 WHERE id = $2")
-            .bind(date)
-            .bind(post_id)
-            .execute(&data.db)
+        slugs::change_slug(&mut tx, post_id, slug)
             .await
-            .is_err()
-        {
-            return HttpResponse::InternalServerError().json("Failed to update post date");
-        }
+            .map_err(ApiError::on_unique(SLUG_IN_USE))?;
     }
-    
+
     if let Some(content) = &post_data.content {
-        let title = if let Some(t) = &post_data.title {
-            t.clone()
-        } else {
-            match sqlx::query("SELECT title FROM posts WHERE id = $1")
-                .bind(post_id)
-                .fetch_one(&data.db)
-                .await
-            {
-                Ok(row) => row.get(0),
-                Err(_) => "Post Title".to_string()
-            }
-        };
-        
-        let html_content = format!(
-            "<div class='post-container'><h1 class='post-title'>{}</h1><div class='post-content'>{}</div></div>",
-            title, content
-        );
-        
-        if std::fs::write(format!("./assets/posts/{}.html", post_id), html_content).is_err() {
-            return HttpResponse::InternalServerError().json("Failed to update post file");
+        // Only record a revision when the body actually changed.
+        let current = latest_revision_content(&mut *tx, post_id).await?;
+        if current.as_deref() != Some(content.as_str()) {
+            insert_revision(&mut *tx, post_id, content, &author_id).await?;
         }
     }
-    
-    HttpResponse::Ok().json("Post updated successfully")
+
+    if post_data.title.is_some() || post_data.content.is_some() {
+        refresh_search_vector(&mut *tx, post_id).await?;
+    }
+
+    if let Some(tags) = &post_data.tags {
+        taxonomy::set_post_tags(&mut tx, post_id, tags).await?;
+    }
+    if let Some(categories) = &post_data.categories {
+        taxonomy::set_post_categories(&mut tx, post_id, categories).await?;
+    }
+    if let Some(series) = &post_data.series {
+        if !taxonomy::set_post_series(&mut tx, post_id, series.as_ref()).await? {
+            return Err(ApiError::invalid("Series not found"));
+        }
+    }
+
+    tx.commit().await?;
+    Ok(HttpResponse::Ok().json("Post updated successfully"))
 }
 
 #[delete("/admin/posts/{id}")]
 pub async fn delete_post(
-    session: Session,
+    user: AuthenticatedUser,
     path: web::Path<i32>,
     data: web::Data<AppState>,
-) -> impl Responder {
-    if !is_admin(&session) {
-        return HttpResponse::Unauthorized().json("Admin access required");
-    }
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
 
     let post_id = path.into_inner();
-    
-    match sqlx::query("DELETE FROM posts WHERE id = $1")
+
+    // Revisions are removed by the ON DELETE CASCADE on post_revisions.post_id.
+    let result = sqlx::query("DELETE FROM posts WHERE id = $1")
         .bind(post_id)
         .execute(&data.db)
-        .await
-    {
-        Ok(_) => {
-            if std::fs::remove_file(format!("./assets/posts/{}.html", post_id)).is_err() {
-                return HttpResponse::InternalServerError().json("Failed to delete post file");
-            }
-            HttpResponse::Ok().json("Post deleted successfully")
-        }
-        Err(_) => HttpResponse::InternalServerError().json("Failed to delete post")
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("Post not found"));
     }
+    Ok(HttpResponse::Ok().json("Post deleted successfully"))
 }
 
 #[get("/admin/posts/{id}")]
 pub async fn get_post_content(
-    session: Session,
+    user: AuthenticatedUser,
     path: web::Path<i32>,
-) -> impl Responder {
-    if !is_admin(&session) {
-        return HttpResponse::Unauthorized().json("Admin access required");
-    }
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
 
     let post_id = path.into_inner();
-    
-    match std::fs::read_to_string(format!("./assets/posts/{}.html", post_id)) {
-        Ok(content) => HttpResponse::Ok().json(serde_json::json!({ "content": content })),
-        Err(_) => HttpResponse::NotFound().json("Post content not found")
+
+    let row = sqlx::query(
+        "SELECT p.title, r.content FROM posts p \
+         JOIN post_revisions r ON r.post_id = p.id \
+         WHERE p.id = $1 ORDER BY r.id DESC LIMIT 1",
+    )
+    .bind(post_id)
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("Post content not found"))?;
+    let title: String = row.get(0);
+    let content: String = row.get(1);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "title": title, "content": content })))
+}
+
+#[get("/admin/posts/{id}/revisions")]
+pub async fn list_revisions(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+    let cursor = query.cursor::<DateTime<Utc>, i32>()?;
+    let limit = query.limit();
+    let (keyset, order) = Cursor::sql(cursor.as_ref(), "created_at", "id", 3);
+
+    let post_id = path.into_inner();
+
+    let sql = format!(
+        "SELECT id, post_id, author_id, created_at FROM post_revisions \
+         WHERE post_id = $1 AND {keyset} ORDER BY {order} LIMIT $2"
+    );
+    let mut revisions = sqlx::query_as::<_, PostRevision>(&sql)
+        .bind(post_id)
+        .bind(limit + 1);
+    if let Some(cursor) = &cursor {
+        revisions = revisions.bind(cursor.key).bind(cursor.id);
+    }
+
+    let revisions = revisions.fetch_all(&data.db).await?;
+    Ok(HttpResponse::Ok().json(Page::new(
+        &req,
+        limit,
+        cursor.as_ref(),
+        revisions,
+        |revision| (revision.created_at, revision.id),
+    )))
+}
+
+#[get("/admin/posts/{id}/revisions/{from}/diff/{to}")]
+pub async fn diff_revisions(
+    user: AuthenticatedUser,
+    path: web::Path<(i32, i32, i32)>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+
+    let (post_id, from, to) = path.into_inner();
+
+    let rows = sqlx::query(
+        "SELECT id, content FROM post_revisions WHERE post_id = $1 AND id IN ($2, $3)",
+    )
+    .bind(post_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(&data.db)
+    .await?;
+
+    let content_of = |revision_id: i32| {
+        rows.iter()
+            .find(|row| row.get::<i32, _>(0) == revision_id)
+            .map(|row| row.get::<String, _>(1))
+    };
+
+    match (content_of(from), content_of(to)) {
+        (Some(old), Some(new)) => {
+            let diff = similar::TextDiff::from_lines(&old, &new)
+                .unified_diff()
+                .header(&format!("revision {from}"), &format!("revision {to}"))
+                .to_string();
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "from": from, "to": to, "diff": diff })))
+        }
+        _ => Err(ApiError::not_found("Revision not found")),
+    }
+}
+
+#[post("/admin/posts/{id}/revisions/{revision}/restore")]
+pub async fn restore_revision(
+    user: AuthenticatedUser,
+    path: web::Path<(i32, i32)>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+
+    let (post_id, revision_id) = path.into_inner();
+    let author_id = Some(user.id.clone());
+
+    // Restoring appends a new revision with the old source, rendered again so
+    // it picks up any changes to the renderer since. History is never rewritten.
+    let mut tx = data.db.begin().await?;
+
+    let content: String = sqlx::query_scalar(
+        "SELECT content FROM post_revisions WHERE post_id = $1 AND id = $2",
+    )
+    .bind(post_id)
+    .bind(revision_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::not_found("Revision not found"))?;
+    let id = insert_revision(&mut *tx, post_id, &content, &author_id).await?;
+
+    refresh_search_vector(&mut *tx, post_id).await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok()
+        .json(serde_json::json!({ "id": id, "message": "Revision restored successfully" })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::{ProxyNet, Store};
+    use crate::testing;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+
+    #[test]
+    fn publish_at_is_kept_only_while_scheduled() {
+        let at = Utc::now();
+        assert_eq!(schedule(PostStatus::Scheduled, Some(at)).unwrap(), Some(at));
+        assert!(schedule(PostStatus::Scheduled, None).is_err());
+        assert_eq!(schedule(PostStatus::Draft, Some(at)).unwrap(), None);
+        assert_eq!(schedule(PostStatus::Published, None).unwrap(), None);
+    }
+
+    #[test]
+    fn legacy_html_survives_rendering() {
+        let html = "<div class='post-container'><h1 class='post-title'>Old</h1>\
+            <div class='post-content'><p>Intro</p>\n\n    <ul>\n        <li>indented</li>\n    </ul>\n\
+            <pre><code>fn main() {\n\n}</code></pre></div></div>";
+        let source = legacy_post_source("assets/posts/1.html", legacy_post_body(html));
+        assert!(source.starts_with("<!-- Imported as HTML from assets/posts/1.html -->\n"));
+
+        let rendered = markdown::render(&source);
+        assert_eq!(
+            rendered.html,
+            "\n<div class=\"legacy-post\">\n<p>Intro</p>\n\n    <ul>\n        <li>indented</li>\n    </ul>\n\
+             <pre><code>fn main() {\n\n}</code></pre>\n</div>\n"
+        );
+        assert_eq!(rendered.text, "Intro indented fn main() { }");
+    }
+
+    #[actix_web::test]
+    async fn forwarded_clients_are_separate_visitors() {
+        let Some(state) = testing::state().await else {
+            return;
+        };
+        let slug = format!("counted-post-{}", testing::unique());
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO posts (title, slug, published_date, views, status) \
+             VALUES ('Counted post', $1, CURRENT_DATE, 0, 'published') RETURNING id",
+        )
+        .bind(&slug)
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+
+        let limiter = web::Data::new(RateLimiter::new(
+            Store::memory(),
+            vec![ProxyNet::parse("172.16.0.0/12").unwrap()],
+        ));
+        let app = init_service(
+            App::new()
+                .app_data(state.clone())
+                .app_data(limiter)
+                .service(update_views),
+        )
+        .await;
+
+        let mut counted = Vec::new();
+        for client in ["203.0.113.7", "203.0.113.8", "203.0.113.7"] {
+            let request = TestRequest::put()
+                .uri(&format!("/update_views/{slug}"))
+                .peer_addr("172.16.3.4:5000".parse().unwrap())
+                .insert_header(("Fly-Client-IP", client))
+                .insert_header((USER_AGENT, "Mozilla/5.0 (X11; Linux x86_64) Firefox/130.0"))
+                .to_request();
+            let body: serde_json::Value = read_body_json(call_service(&app, request).await).await;
+            counted.push(body["counted"].as_bool().unwrap());
+        }
+        assert_eq!(counted, [true, true, false]);
+
+        let views: i32 = sqlx::query_scalar("SELECT views FROM posts WHERE id = $1")
+            .bind(id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(views, 2);
+
+        sqlx::query("DELETE FROM posts WHERE id = $1")
+            .bind(id)
+            .execute(&state.db)
+            .await
+            .unwrap();
     }
 }
@@ -0,0 +1,138 @@
+use crate::error::ApiError;
+use crate::services::PUBLISHED_FILTER;
+use crate::state::AppState;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::FromRow;
+
+/// Routes served by the SPA that should always be listed.
+const STATIC_ROUTES: &[&str] = &["/", "/blog", "/guestbook", "/about", "/resume"];
+
+/// Posts per sitemap file. Once the site outgrows one file, `/sitemap.xml`
+/// becomes an index pointing at `/sitemaps/{page}.xml`.
+const SITEMAP_PAGE_SIZE: i64 = 1000;
+
+#[derive(FromRow)]
+struct SitemapRow {
+    slug: String,
+    published_date: NaiveDate,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+async fn count_posts(state: &AppState) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM posts p WHERE {PUBLISHED_FILTER}"))
+        .fetch_one(&state.db)
+        .await
+}
+
+/// Sitemap files needed for `total` posts; the first also lists the static
+/// routes, so there is always at least one.
+fn page_count(total: i64) -> i64 {
+    ((total + SITEMAP_PAGE_SIZE - 1) / SITEMAP_PAGE_SIZE).max(1)
+}
+
+async fn fetch_page(state: &AppState, page: i64) -> Result<Vec<SitemapRow>, sqlx::Error> {
+    sqlx::query_as::<_, SitemapRow>(&format!(
+        "SELECT p.slug, p.published_date, \
+         (SELECT MAX(created_at) FROM post_revisions WHERE post_id = p.id) AS updated_at \
+         FROM posts p WHERE {PUBLISHED_FILTER} ORDER BY p.id LIMIT $1 OFFSET $2"
+    ))
+    .bind(SITEMAP_PAGE_SIZE)
+    .bind(page * SITEMAP_PAGE_SIZE)
+    .fetch_all(&state.db)
+    .await
+}
+
+fn render_urlset(site_url: &str, include_static: bool, rows: &[SitemapRow]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">",
+    );
+    if include_static {
+        for route in STATIC_ROUTES {
+            xml.push_str(&format!("<url><loc>{site_url}{route}</loc></url>"));
+        }
+    }
+    for row in rows {
+        let lastmod = match row.updated_at {
+            Some(updated) => updated.date_naive().max(row.published_date),
+            None => row.published_date,
+        };
+        xml.push_str(&format!(
+            "<url><loc>{site_url}/blog/{}</loc><lastmod>{}</lastmod></url>",
+            row.slug,
+            lastmod.format("%Y-%m-%d")
+        ));
+    }
+    xml.push_str("</urlset>");
+    xml
+}
+
+fn render_index(site_url: &str, pages: i64) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">",
+    );
+    for page in 1..=pages {
+        xml.push_str(&format!(
+            "<sitemap><loc>{site_url}/sitemaps/{page}.xml</loc></sitemap>"
+        ));
+    }
+    xml.push_str("</sitemapindex>");
+    xml
+}
+
+fn xml_response(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/xml; charset=utf-8")
+        .insert_header((CACHE_CONTROL, "public, max-age=3600"))
+        .body(body)
+}
+
+#[get("/sitemap.xml")]
+pub async fn sitemap_index(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let total = count_posts(&data).await?;
+
+    let pages = page_count(total);
+    if pages > 1 {
+        return Ok(xml_response(render_index(&data.config.site_url, pages)));
+    }
+
+    let rows = fetch_page(&data, 0).await?;
+    Ok(xml_response(render_urlset(&data.config.site_url, true, &rows)))
+}
+
+#[get("/sitemaps/{page}.xml")]
+pub async fn sitemap_page(
+    path: web::Path<i64>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let page = path.into_inner();
+    let pages = page_count(count_posts(&data).await?);
+    if !(1..=pages).contains(&page) {
+        return Err(ApiError::not_found("Sitemap not found"));
+    }
+
+    let rows = fetch_page(&data, page - 1).await?;
+    Ok(xml_response(render_urlset(&data.config.site_url, page == 1, &rows)))
+}
+
+/// Only production may be crawled; every other environment disallows everything
+/// so staging copies never compete with the live site in search results.
+#[get("/robots.txt")]
+pub async fn robots(data: web::Data<AppState>) -> HttpResponse {
+    let body = if data.config.environment == "production" {
+        format!(
+            "User-agent: *\nAllow: /\nDisallow: /api/\nDisallow: /auth/\n\nSitemap: {}/sitemap.xml\n",
+            data.config.site_url
+        )
+    } else {
+        String::from("User-agent: *\nDisallow: /\n")
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .insert_header((CACHE_CONTROL, "public, max-age=3600"))
+        .body(body)
+}
@@ -0,0 +1,331 @@
+use crate::error::ApiError;
+use crate::markdown::{self, slugify};
+use crate::state::AppState;
+use actix_web::http::header::LOCATION;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::NaiveDate;
+use sqlx::{FromRow, PgConnection};
+
+const MAX_SLUG_LENGTH: usize = 200;
+
+/// A post found by [`resolve_post`]. `slug` is always the current one, so a
+/// caller can redirect whenever it differs from what was requested.
+#[derive(FromRow)]
+pub struct ResolvedPost {
+    pub id: i32,
+    pub slug: String,
+    pub public: bool,
+}
+
+/// Normalizes a slug supplied by an editor. Purely numeric slugs are rejected
+/// because `/posts/{key}` also accepts numeric ids.
+pub fn normalize_slug(input: &str) -> Result<String, &'static str> {
+    let slug = slugify(input);
+    if slug.is_empty() || slug.len() > MAX_SLUG_LENGTH {
+        return Err("Invalid slug");
+    }
+    if slug.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        return Err("Slug must contain a letter");
+    }
+    Ok(slug)
+}
+
+fn base_slug(title: &str) -> String {
+    let slug: String = slugify(title).chars().take(MAX_SLUG_LENGTH - 8).collect();
+    let slug = slug.trim_end_matches('-');
+    if slug.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        format!("post-{slug}").trim_end_matches('-').to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Whether `slug` is free for `post_id` (or for a new post when `None`). Slugs
+/// a post used to have stay reserved for it so their redirects keep working.
+pub async fn slug_available(
+    conn: &mut PgConnection,
+    slug: &str,
+    post_id: Option<i32>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT NOT EXISTS (SELECT 1 FROM posts WHERE slug = $1 AND id IS DISTINCT FROM $2) \
+         AND NOT EXISTS (SELECT 1 FROM post_slug_history WHERE slug = $1 AND post_id IS DISTINCT FROM $2)",
+    )
+    .bind(slug)
+    .bind(post_id)
+    .fetch_one(&mut *conn)
+    .await
+}
+
+/// Derives a free slug from a title, appending `-2`, `-3`, … on collision.
+pub async fn generate_slug(conn: &mut PgConnection, title: &str) -> Result<String, sqlx::Error> {
+    let base = base_slug(title);
+    let taken: Vec<String> = sqlx::query_scalar(
+        "SELECT slug FROM posts WHERE slug = $1 OR slug LIKE $1 || '-%' \
+         UNION SELECT slug FROM post_slug_history WHERE slug = $1 OR slug LIKE $1 || '-%'",
+    )
+    .bind(&base)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if !taken.contains(&base) {
+        return Ok(base);
+    }
+    let mut suffix = 2;
+    loop {
+        let candidate = format!("{base}-{suffix}");
+        if !taken.contains(&candidate) {
+            return Ok(candidate);
+        }
+        suffix += 1;
+    }
+}
+
+/// Gives a post a new slug, keeping the old one in `post_slug_history` so it
+/// redirects. Moving back to a previous slug takes it out of the history.
+pub async fn change_slug(
+    conn: &mut PgConnection,
+    post_id: i32,
+    slug: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO post_slug_history (slug, post_id) \
+         SELECT slug, id FROM posts WHERE id = $1 AND slug <> $2 \
+         ON CONFLICT (slug) DO UPDATE SET post_id = EXCLUDED.post_id, created_at = now()",
+    )
+    .bind(post_id)
+    .bind(slug)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("DELETE FROM post_slug_history WHERE slug = $1")
+        .bind(slug)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("UPDATE posts SET slug = $1 WHERE id = $2")
+        .bind(slug)
+        .bind(post_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Looks a post up by current slug, previous slug or numeric id, in that order.
+pub async fn resolve_post(
+    executor: impl sqlx::PgExecutor<'_>,
+    key: &str,
+) -> Result<Option<ResolvedPost>, sqlx::Error> {
+    let id: Option<i32> = key.parse().ok();
+    sqlx::query_as::<_, ResolvedPost>(
+        "SELECT p.id, p.slug, p.status IN ('published', 'unlisted', 'archived') AS public \
+         FROM ( \
+             SELECT id, 1 AS rank FROM posts WHERE slug = $1 \
+             UNION ALL SELECT post_id, 2 FROM post_slug_history WHERE slug = $1 \
+             UNION ALL SELECT id, 3 FROM posts WHERE id = $2 \
+         ) m JOIN posts p ON p.id = m.id ORDER BY m.rank LIMIT 1",
+    )
+    .bind(key)
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Like [`resolve_post`], but fails with 404 unless anyone may read the post.
+#[tracing::instrument(skip_all, fields(key = %key))]
+pub async fn public_post(
+    executor: impl sqlx::PgExecutor<'_>,
+    key: &str,
+) -> Result<ResolvedPost, ApiError> {
+    match resolve_post(executor, key).await? {
+        Some(post) if post.public => Ok(post),
+        _ => Err(ApiError::not_found("Post not found")),
+    }
+}
+
+/// Builds a 301 to `path`, carrying over the original query string.
+pub fn permanent_redirect(req: &HttpRequest, path: &str) -> HttpResponse {
+    let location = match req.query_string() {
+        "" => path.to_string(),
+        query => format!("{path}?{query}"),
+    };
+    HttpResponse::MovedPermanently()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/// Exported page that every post is rendered into. Its `post-title`,
+/// `post-meta` and `post-body` elements are left empty for the server.
+const POST_PAGE: &str = "blog/post/index.html";
+
+/// Used when the frontend hasn't been built, so posts still render.
+const FALLBACK_POST_PAGE: &str = "<!DOCTYPE html><html lang=\"en\"><head>\
+    <meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+    <title></title></head><body><article><h1 id=\"post-title\"></h1><div id=\"post-meta\"></div>\
+    <div id=\"post-body\"></div></article></body></html>";
+
+/// Inserts `html` into the empty element with the given id.
+fn fill(page: &mut String, id: &str, html: &str) -> bool {
+    let Some(start) = page.find(&format!(" id=\"{id}\"")) else {
+        return false;
+    };
+    let Some(end) = page[start..].find('>') else {
+        return false;
+    };
+    page.insert_str(start + end + 1, html);
+    true
+}
+
+fn set_title(page: &mut String, title: &str) -> bool {
+    let (Some(start), Some(end)) = (page.find("<title>"), page.find("</title>")) else {
+        return false;
+    };
+    if end < start {
+        return false;
+    }
+    page.replace_range(start + "<title>".len()..end, title);
+    true
+}
+
+/// Renders a post into `shell`, or into [`FALLBACK_POST_PAGE`] when the shell
+/// is missing or doesn't have the expected slots.
+fn post_page(shell: Option<String>, title: &str, meta: &str, body: &str) -> String {
+    let title = markdown::escape_html(title);
+    let render = |mut page: String| {
+        let filled = set_title(&mut page, &format!("{title} - Ray Space"))
+            && fill(&mut page, "post-title", &title)
+            && fill(&mut page, "post-meta", meta)
+            && fill(&mut page, "post-body", body);
+        filled.then_some(page)
+    };
+    shell
+        .and_then(render)
+        .or_else(|| render(FALLBACK_POST_PAGE.to_string()))
+        .unwrap_or_default()
+}
+
+/// Serves a post at its canonical URL, rendered on the server. Old slugs and
+/// numeric ids are sent to the canonical URL.
+#[get("/blog/{key}")]
+pub async fn blog_post_page(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let key = path.into_inner();
+
+    let post = public_post(&data.db, &key).await?;
+    if post.slug != key {
+        return Ok(permanent_redirect(&req, &format!("/blog/{}", post.slug)));
+    }
+
+    let (title, published_date, views, toc, rendered): (String, NaiveDate, i32, String, String) =
+        sqlx::query_as(
+            "SELECT p.title, p.published_date, p.views, r.toc, r.rendered FROM posts p \
+             JOIN post_revisions r ON r.post_id = p.id \
+             WHERE p.id = $1 ORDER BY r.id DESC LIMIT 1",
+        )
+        .bind(post.id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Post not found"))?;
+
+    let meta = format!(
+        "<span>Published: {}</span><span>Views: {views}</span>",
+        published_date.format("%B %-d, %Y")
+    );
+    let shell = tokio::fs::read_to_string(format!("{}/{POST_PAGE}", crate::STATIC_DIR))
+        .await
+        .ok();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(post_page(shell, &title, &meta, &format!("{toc}{rendered}"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    #[test]
+    fn fills_the_exported_page() {
+        let shell = "<html><head><title>Ray Space</title></head><body><main>\
+            <h1 id=\"post-title\" class=\"text-3xl\"></h1><div id=\"post-meta\"></div>\
+            <div id=\"post-body\" class=\"post-content\"></div></main></body></html>";
+        let page = post_page(
+            Some(shell.to_string()),
+            "A & B",
+            "<span>meta</span>",
+            "<p>body</p>",
+        );
+        assert_eq!(
+            page,
+            "<html><head><title>A &amp; B - Ray Space</title></head><body><main>\
+             <h1 id=\"post-title\" class=\"text-3xl\">A &amp; B</h1><div id=\"post-meta\"><span>meta</span></div>\
+             <div id=\"post-body\" class=\"post-content\"><p>body</p></div></main></body></html>"
+        );
+
+        let fallback = post_page(Some("<html></html>".to_string()), "A", "", "<p>body</p>");
+        assert!(fallback.starts_with("<!DOCTYPE html>"));
+        assert!(fallback.contains("<div id=\"post-body\"><p>body</p></div>"));
+    }
+
+    #[actix_web::test]
+    async fn canonical_url_serves_the_post() {
+        let Some(state) = testing::state().await else {
+            return;
+        };
+        let slug = format!("rendered-post-{}", testing::unique());
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO posts (title, slug, published_date, views, status) \
+             VALUES ('Rendered post', $1, CURRENT_DATE, 0, 'published') RETURNING id",
+        )
+        .bind(&slug)
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO post_revisions (post_id, content, rendered, toc, plain_text) \
+             VALUES ($1, 'Hello', '<p>Hello from the post body</p>', '', 'Hello')",
+        )
+        .bind(id)
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        let app = init_service(App::new().app_data(state.clone()).service(blog_post_page)).await;
+
+        let response = call_service(
+            &app,
+            TestRequest::get()
+                .uri(&format!("/blog/{slug}"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("<h1 id=\"post-title\""));
+        assert!(body.contains("Rendered post</h1>"));
+        assert!(body.contains("<p>Hello from the post body</p>"));
+
+        let response = call_service(
+            &app,
+            TestRequest::get().uri(&format!("/blog/{id}")).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), 301);
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            format!("/blog/{slug}").as_str()
+        );
+
+        sqlx::query("DELETE FROM posts WHERE id = $1")
+            .bind(id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+    }
+}
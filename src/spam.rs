@@ -0,0 +1,570 @@
+use regex::Regex;
+use serde::Serialize;
+use sqlx::types::Json;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+/// Comments scoring at least this much are held for review by default.
+pub const DEFAULT_QUEUE_SCORE: f32 = 1.0;
+/// Comments scoring at least this much are rejected outright by default.
+pub const DEFAULT_REJECT_SCORE: f32 = 2.5;
+
+const BLOCKLIST_HIT_SCORE: f32 = 2.0;
+const DUPLICATE_SCORE: f32 = 1.5;
+const REPETITION_SCORE: f32 = 1.0;
+const SCORE_PER_EXTRA_LINK: f32 = 0.5;
+const FREE_LINKS: usize = 1;
+const CLASSIFIER_WEIGHT: f32 = 5.0;
+/// The classifier stays silent until it has seen this many examples of each label.
+const MIN_TRAINING_DOCUMENTS: i64 = 5;
+const MAX_TOKENS: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Accept,
+    Queue,
+    Reject,
+}
+
+impl Decision {
+    fn as_str(self) -> &'static str {
+        match self {
+            Decision::Accept => "accept",
+            Decision::Queue => "queue",
+            Decision::Reject => "reject",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Label {
+    Spam,
+    Ham,
+}
+
+impl Label {
+    fn as_str(self) -> &'static str {
+        match self {
+            Label::Spam => "spam",
+            Label::Ham => "ham",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RuleScore {
+    pub rule: &'static str,
+    pub score: f32,
+}
+
+#[derive(Serialize)]
+pub struct Verdict {
+    pub score: f32,
+    pub decision: Decision,
+    pub rules: Vec<RuleScore>,
+}
+
+/// Token counts from past moderator decisions, loaded for the tokens of the
+/// comment being scored.
+#[derive(Default)]
+pub struct ClassifierStats {
+    spam_documents: i64,
+    ham_documents: i64,
+    tokens: HashMap<String, (i64, i64)>,
+}
+
+/// Everything a rule may look at. Gathered once per comment so rules stay
+/// synchronous and cheap.
+pub struct CommentContext<'a> {
+    /// The sanitized comment body.
+    pub text: &'a str,
+    /// The author's other comments from the last day.
+    pub recent_by_author: Vec<String>,
+    pub classifier: ClassifierStats,
+}
+
+/// One step of the spam pipeline. Returns 0.0 for "nothing suspicious";
+/// scores from every rule are summed into the verdict.
+pub trait SpamRule: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn score(&self, context: &CommentContext) -> f32;
+}
+
+pub struct LinkCount;
+
+impl SpamRule for LinkCount {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn score(&self, context: &CommentContext) -> f32 {
+        let text = context.text.to_lowercase();
+        let links = ["http://", "https://", "www."]
+            .iter()
+            .map(|marker| text.matches(marker).count())
+            .sum::<usize>();
+        links.saturating_sub(FREE_LINKS) as f32 * SCORE_PER_EXTRA_LINK
+    }
+}
+
+/// Catches the same message posted again and bodies padded with one word or
+/// character repeated over and over.
+pub struct RepeatedContent;
+
+impl SpamRule for RepeatedContent {
+    fn name(&self) -> &'static str {
+        "repetition"
+    }
+
+    fn score(&self, context: &CommentContext) -> f32 {
+        let text = normalize(context.text);
+        let mut score = 0.0;
+
+        if !text.is_empty()
+            && context
+                .recent_by_author
+                .iter()
+                .any(|previous| normalize(previous) == text)
+        {
+            score += DUPLICATE_SCORE;
+        }
+
+        let words: Vec<&str> = text.split(' ').filter(|word| !word.is_empty()).collect();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for word in &words {
+            *counts.entry(word).or_default() += 1;
+        }
+        let most_common = counts.values().copied().max().unwrap_or(0);
+        let repeated_word = words.len() >= 8 && most_common * 2 > words.len();
+
+        let mut run = 0;
+        let mut longest_run = 0;
+        let mut previous = None;
+        for c in text.chars() {
+            run = if Some(c) == previous { run + 1 } else { 1 };
+            longest_run = longest_run.max(run);
+            previous = Some(c);
+        }
+
+        if repeated_word || longest_run >= 10 {
+            score += REPETITION_SCORE;
+        }
+        score
+    }
+}
+
+pub struct Blocklist {
+    words: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Blocklist {
+    pub fn empty() -> Self {
+        Blocklist {
+            words: Vec::new(),
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Reads one entry per line. Lines starting with `re:` are regular
+    /// expressions, `#` starts a comment, anything else is a case-insensitive word.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let mut blocklist = Blocklist::empty();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix("re:") {
+                Some(pattern) => blocklist.patterns.push(
+                    Regex::new(&format!("(?i){}", pattern.trim()))
+                        .map_err(|e| format!("{path}: {e}"))?,
+                ),
+                None => blocklist.words.push(line.to_lowercase()),
+            }
+        }
+        Ok(blocklist)
+    }
+}
+
+impl SpamRule for Blocklist {
+    fn name(&self) -> &'static str {
+        "blocklist"
+    }
+
+    fn score(&self, context: &CommentContext) -> f32 {
+        let text = normalize(context.text);
+        let words: HashSet<&str> = text.split(' ').collect();
+        let hits = self
+            .words
+            .iter()
+            .filter(|entry| {
+                if entry.contains(' ') {
+                    text.contains(entry.as_str())
+                } else {
+                    words.contains(entry.as_str())
+                }
+            })
+            .count()
+            + self
+                .patterns
+                .iter()
+                .filter(|pattern| pattern.is_match(context.text))
+                .count();
+        hits as f32 * BLOCKLIST_HIT_SCORE
+    }
+}
+
+/// Naive Bayes over the tokens of comments moderators approved (ham) or
+/// rejected (spam). Only a confident spam call contributes to the score.
+pub struct Classifier;
+
+impl SpamRule for Classifier {
+    fn name(&self) -> &'static str {
+        "classifier"
+    }
+
+    fn score(&self, context: &CommentContext) -> f32 {
+        let stats = &context.classifier;
+        if stats.spam_documents < MIN_TRAINING_DOCUMENTS
+            || stats.ham_documents < MIN_TRAINING_DOCUMENTS
+        {
+            return 0.0;
+        }
+
+        let spam_total = stats.spam_documents as f64;
+        let ham_total = stats.ham_documents as f64;
+        let mut spam_log = (spam_total / (spam_total + ham_total)).ln();
+        let mut ham_log = (ham_total / (spam_total + ham_total)).ln();
+        for token in tokenize(context.text) {
+            let (spam, ham) = stats.tokens.get(&token).copied().unwrap_or((0, 0));
+            spam_log += ((spam as f64 + 1.0) / (spam_total + 2.0)).ln();
+            ham_log += ((ham as f64 + 1.0) / (ham_total + 2.0)).ln();
+        }
+
+        let probability = 1.0 / (1.0 + (ham_log - spam_log).exp());
+        if probability > 0.5 {
+            (probability - 0.5) as f32 * CLASSIFIER_WEIGHT
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The configured rule set and the score thresholds that turn a total into a
+/// decision.
+pub struct SpamFilter {
+    rules: Vec<Box<dyn SpamRule>>,
+    queue_score: f32,
+    reject_score: f32,
+}
+
+impl SpamFilter {
+    pub fn new(blocklist: Blocklist, queue_score: f32, reject_score: f32) -> Self {
+        SpamFilter {
+            rules: vec![
+                Box::new(LinkCount),
+                Box::new(RepeatedContent),
+                Box::new(blocklist),
+                Box::new(Classifier),
+            ],
+            queue_score,
+            reject_score,
+        }
+    }
+
+    pub fn judge(&self, context: &CommentContext) -> Verdict {
+        let rules: Vec<RuleScore> = self
+            .rules
+            .iter()
+            .map(|rule| RuleScore {
+                rule: rule.name(),
+                score: rule.score(context),
+            })
+            .filter(|result| result.score > 0.0)
+            .collect();
+        let score: f32 = rules.iter().map(|result| result.score).sum();
+        let decision = if score >= self.reject_score {
+            Decision::Reject
+        } else if score >= self.queue_score {
+            Decision::Queue
+        } else {
+            Decision::Accept
+        };
+        Verdict {
+            score,
+            decision,
+            rules,
+        }
+    }
+
+    /// Scores a sanitized comment body written by `userid`. `comment_id` is set
+    /// when re-checking an edit so the comment is not compared with itself.
+    pub async fn evaluate(
+        &self,
+        db: &sqlx::PgPool,
+        userid: &str,
+        comment_id: Option<i32>,
+        text: &str,
+    ) -> Result<Verdict, sqlx::Error> {
+        let recent_by_author: Vec<String> = sqlx::query_scalar(
+            "SELECT comment FROM comments WHERE userid = $1 AND id IS DISTINCT FROM $2 \
+             AND timestamp > now() - interval '1 day' ORDER BY id DESC LIMIT 20",
+        )
+        .bind(userid)
+        .bind(comment_id)
+        .fetch_all(db)
+        .await?;
+
+        let classifier = load_classifier(db, &tokenize(text)).await?;
+        let context = CommentContext {
+            text,
+            recent_by_author,
+            classifier,
+        };
+        Ok(self.judge(&context))
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Distinct lowercase words of 2–30 characters; each counts once per comment.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| (2..=30).contains(&token.chars().count()))
+        .filter(|token| seen.insert(token.to_string()))
+        .take(MAX_TOKENS)
+        .map(str::to_string)
+        .collect()
+}
+
+async fn load_classifier(
+    db: &sqlx::PgPool,
+    tokens: &[String],
+) -> Result<ClassifierStats, sqlx::Error> {
+    let totals: Vec<(String, i64)> =
+        sqlx::query_as("SELECT label, COUNT(*) FROM spam_training GROUP BY label")
+            .fetch_all(db)
+            .await?;
+    let rows: Vec<(String, i32, i32)> =
+        sqlx::query_as("SELECT token, spam, ham FROM spam_tokens WHERE token = ANY($1)")
+            .bind(tokens)
+            .fetch_all(db)
+            .await?;
+
+    let mut stats = ClassifierStats::default();
+    for (label, count) in totals {
+        match label.as_str() {
+            "spam" => stats.spam_documents = count,
+            "ham" => stats.ham_documents = count,
+            _ => {}
+        }
+    }
+    stats.tokens = rows
+        .into_iter()
+        .map(|(token, spam, ham)| (token, (spam as i64, ham as i64)))
+        .collect();
+    Ok(stats)
+}
+
+/// Stores the verdict next to the comment so moderators can see why it was
+/// held or rejected. Edits replace the previous verdict.
+#[tracing::instrument(skip_all, fields(comment_id = comment_id))]
+pub async fn record_verdict(
+    executor: impl sqlx::PgExecutor<'_>,
+    comment_id: i32,
+    verdict: &Verdict,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO comment_spam_verdicts (comment_id, score, decision, rules) \
+         VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (comment_id) DO UPDATE SET score = EXCLUDED.score, \
+         decision = EXCLUDED.decision, rules = EXCLUDED.rules, created_at = now()",
+    )
+    .bind(comment_id)
+    .bind(verdict.score)
+    .bind(verdict.decision.as_str())
+    .bind(Json(&verdict.rules))
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Feeds a moderator decision to the classifier. Re-labelling a comment
+/// moves its tokens from one column to the other instead of counting twice.
+pub async fn train(
+    conn: &mut sqlx::PgConnection,
+    comment_id: i32,
+    label: Label,
+) -> Result<(), sqlx::Error> {
+    let text: Option<String> = sqlx::query_scalar("SELECT comment FROM comments WHERE id = $1")
+        .bind(comment_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let text = match text {
+        Some(text) => text,
+        None => return Ok(()),
+    };
+    let previous: Option<String> =
+        sqlx::query_scalar("SELECT label FROM spam_training WHERE comment_id = $1")
+            .bind(comment_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+    if previous.as_deref() == Some(label.as_str()) {
+        return Ok(());
+    }
+
+    let tokens = tokenize(&text);
+    let (spam_delta, ham_delta) = match (label, previous.is_some()) {
+        (Label::Spam, false) => (1, 0),
+        (Label::Ham, false) => (0, 1),
+        (Label::Spam, true) => (1, -1),
+        (Label::Ham, true) => (-1, 1),
+    };
+
+    sqlx::query(
+        "INSERT INTO spam_tokens (token, spam, ham) \
+         SELECT token, GREATEST($2, 0), GREATEST($3, 0) FROM UNNEST($1::text[]) AS token \
+         ON CONFLICT (token) DO UPDATE SET spam = GREATEST(spam_tokens.spam + $2, 0), \
+         ham = GREATEST(spam_tokens.ham + $3, 0)",
+    )
+    .bind(&tokens)
+    .bind(spam_delta)
+    .bind(ham_delta)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO spam_training (comment_id, label) VALUES ($1, $2) \
+         ON CONFLICT (comment_id) DO UPDATE SET label = EXCLUDED.label",
+    )
+    .bind(comment_id)
+    .bind(label.as_str())
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(text: &str) -> CommentContext<'_> {
+        CommentContext {
+            text,
+            recent_by_author: Vec::new(),
+            classifier: ClassifierStats::default(),
+        }
+    }
+
+    fn filter(words: &[&str]) -> SpamFilter {
+        let blocklist = Blocklist {
+            words: words.iter().map(|word| word.to_string()).collect(),
+            patterns: vec![Regex::new("(?i)buy\\s+now").unwrap()],
+        };
+        SpamFilter::new(blocklist, DEFAULT_QUEUE_SCORE, DEFAULT_REJECT_SCORE)
+    }
+
+    fn trained(tokens: &[(&str, i64, i64)], documents: i64) -> ClassifierStats {
+        ClassifierStats {
+            spam_documents: documents,
+            ham_documents: documents,
+            tokens: tokens
+                .iter()
+                .map(|&(token, spam, ham)| (token.to_string(), (spam, ham)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn ordinary_comments_are_accepted() {
+        let verdict = filter(&["casino"]).judge(&context(
+            "Great post! The docs at https://docs.rs/actix-web helped too.",
+        ));
+        assert_eq!(verdict.decision, Decision::Accept);
+        assert_eq!(verdict.score, 0.0);
+        assert!(verdict.rules.is_empty());
+    }
+
+    #[test]
+    fn extra_links_are_queued() {
+        let verdict = filter(&[]).judge(&context(
+            "see http://a.example https://b.example www.c.example",
+        ));
+        assert_eq!(verdict.score, 2.0 * SCORE_PER_EXTRA_LINK);
+        assert_eq!(verdict.decision, Decision::Queue);
+        assert_eq!(verdict.rules[0].rule, "links");
+    }
+
+    #[test]
+    fn rule_scores_add_up_to_a_rejection() {
+        let filter = filter(&["casino", "free spins"]);
+        let single = filter.judge(&context("Best Casino in town"));
+        assert_eq!(single.score, BLOCKLIST_HIT_SCORE);
+        assert_eq!(single.decision, Decision::Queue);
+
+        let verdict = filter.judge(&context("Casino FREE  spins, buy now"));
+        assert_eq!(verdict.score, 3.0 * BLOCKLIST_HIT_SCORE);
+        assert_eq!(verdict.decision, Decision::Reject);
+    }
+
+    #[test]
+    fn repeats_are_scored() {
+        let mut duplicate = context("Hello  there");
+        duplicate.recent_by_author = vec!["hello there".to_string()];
+        assert_eq!(RepeatedContent.score(&duplicate), DUPLICATE_SCORE);
+
+        let padded = context("spam spam spam spam spam eggs spam ham");
+        assert_eq!(RepeatedContent.score(&padded), REPETITION_SCORE);
+        assert_eq!(
+            RepeatedContent.score(&context("!!!!!!!!!!")),
+            REPETITION_SCORE
+        );
+        assert_eq!(RepeatedContent.score(&context("!!!!!!!!!")), 0.0);
+    }
+
+    #[test]
+    fn classifier_needs_training() {
+        let mut context = context("cheap pills");
+        context.classifier = trained(
+            &[("cheap", 9, 0), ("pills", 9, 0)],
+            MIN_TRAINING_DOCUMENTS - 1,
+        );
+        assert_eq!(Classifier.score(&context), 0.0);
+    }
+
+    #[test]
+    fn classifier_only_scores_spam() {
+        let stats = || trained(&[("cheap", 9, 0), ("pills", 9, 0), ("rust", 0, 9)], 10);
+
+        let mut spammy = context("cheap pills");
+        spammy.classifier = stats();
+        let score = Classifier.score(&spammy);
+        assert!(score > 0.0 && score <= 0.5 * CLASSIFIER_WEIGHT);
+
+        let mut hammy = context("rust");
+        hammy.classifier = stats();
+        assert_eq!(Classifier.score(&hammy), 0.0);
+
+        // Unknown words leave the prior, which is even here.
+        let mut unknown = context("hello");
+        unknown.classifier = stats();
+        assert_eq!(Classifier.score(&unknown), 0.0);
+    }
+
+    #[test]
+    fn tokens_are_distinct_words() {
+        assert_eq!(
+            tokenize("Rust, rust & a RUST-y crate"),
+            vec!["rust", "crate"]
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::spam::SpamFilter;
 use chrono::{DateTime, Utc};
 use sqlx::{Pool, Postgres};
 use std::sync::{Arc, RwLock};
@@ -6,6 +9,9 @@ use std::sync::{Arc, RwLock};
 pub struct StarCache {
     pub star_count: i32,
     pub last_fetched: DateTime<Utc>,
+    /// The most recent call to the GitHub API and why it failed, if it did.
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
 }
 
 impl StarCache {
@@ -13,25 +19,29 @@ impl StarCache {
         StarCache {
             star_count: 0,
             last_fetched: Utc::now(),
+            last_attempt: None,
+            last_error: None,
         }
     }
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub github_client_id: String,
-    pub github_client_secret: String,
+    pub config: Arc<Config>,
     pub db: Pool<Postgres>,
+    pub spam: Arc<SpamFilter>,
     pub star_cache: Arc<RwLock<StarCache>>,
+    pub metrics: Arc<Metrics>,
 }
 
 impl AppState {
-    pub fn new(github_client_id: String, github_client_secret: String, db: Pool<Postgres>) -> Self {
+    pub fn new(config: Config, db: Pool<Postgres>, spam: SpamFilter) -> Self {
         AppState {
-            github_client_id,
-            github_client_secret,
+            config: Arc::new(config),
             db,
+            spam: Arc::new(spam),
             star_cache: Arc::new(RwLock::new(StarCache::new())),
+            metrics: Arc::new(Metrics::new()),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,179 @@
+use actix_web::rt::task::JoinHandle;
+use futures_util::FutureExt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Delay before the first retry of a failed job. It doubles with each
+/// consecutive failure, up to `MAX_BACKOFF` (or the job's interval, if
+/// shorter).
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// A long-running task that stayed up this long is considered healthy again,
+/// so its next failure starts over at `MIN_BACKOFF`.
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+
+/// Completes once shutdown has begun. Cheap to clone into tasks and request
+/// handlers that need to wind down early, like SSE streams.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub async fn wait(&mut self) {
+        // An error means the supervisor is gone, which is shutdown too.
+        let _ = self.0.wait_for(|stopping| *stopping).await;
+    }
+}
+
+/// Owns the app's background work: periodic jobs and long-running tasks.
+/// Failures are reported and retried with exponential backoff; a panic counts
+/// as a failure. On shutdown, jobs finish the run in progress and stop.
+pub struct Supervisor {
+    stop: watch::Sender<bool>,
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+fn backoff(failures: u32) -> Duration {
+    MIN_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// Runs one attempt, reporting how it failed. A panic is caught here rather
+/// than taking the supervising loop down with it.
+async fn attempt<Fut, E>(name: &'static str, run: Fut) -> bool
+where
+    Fut: Future<Output = Result<(), E>>,
+    E: std::error::Error + 'static,
+{
+    match AssertUnwindSafe(run).catch_unwind().await {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            sentry::capture_error(&e);
+            tracing::error!(job = name, error = %e, "Background job failed");
+            false
+        }
+        // Sentry's panic handler has already reported it.
+        Err(_) => {
+            tracing::error!(job = name, "Background job panicked");
+            false
+        }
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            stop: watch::channel(false).0,
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn shutdown(&self) -> Shutdown {
+        Shutdown(self.stop.subscribe())
+    }
+
+    /// Runs `job` now and then every `interval`. After a failure it is
+    /// retried sooner, backing off while it keeps failing.
+    pub fn every<F, Fut, E>(&mut self, name: &'static str, interval: Duration, job: F)
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = Result<(), E>> + 'static,
+        E: std::error::Error + 'static,
+    {
+        let mut shutdown = self.shutdown();
+        let task = actix_web::rt::spawn(async move {
+            let mut failures = 0;
+            loop {
+                let delay = if attempt(name, job()).await {
+                    failures = 0;
+                    interval
+                } else {
+                    failures += 1;
+                    backoff(failures).min(interval)
+                };
+                tokio::select! {
+                    _ = shutdown.wait() => break,
+                    _ = actix_web::rt::time::sleep(delay) => {}
+                }
+            }
+        });
+        self.tasks.push((name, task));
+    }
+
+    /// Keeps a task that is meant to run until shutdown alive, restarting it
+    /// with backoff whenever it fails. Unlike a job it is cancelled as soon as
+    /// shutdown begins.
+    pub fn spawn<F, Fut, E>(&mut self, name: &'static str, task: F)
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = Result<(), E>> + 'static,
+        E: std::error::Error + 'static,
+    {
+        let mut shutdown = self.shutdown();
+        let task = actix_web::rt::spawn(async move {
+            let mut failures = 0;
+            loop {
+                let started = Instant::now();
+                let succeeded = tokio::select! {
+                    _ = shutdown.wait() => break,
+                    succeeded = attempt(name, task()) => succeeded,
+                };
+                // Returning means it has nothing left to do.
+                if succeeded {
+                    break;
+                }
+                if started.elapsed() > HEALTHY_RUN {
+                    failures = 0;
+                }
+                failures += 1;
+                tokio::select! {
+                    _ = shutdown.wait() => break,
+                    _ = actix_web::rt::time::sleep(backoff(failures)) => {}
+                }
+            }
+        });
+        self.tasks.push((name, task));
+    }
+
+    /// Signals every job and task to stop. In-progress job runs carry on.
+    pub fn stop(&self) {
+        self.stop.send_replace(true);
+    }
+
+    /// Stops everything and waits up to `timeout` for runs in progress to
+    /// finish; whatever is still running then is aborted.
+    pub async fn join(self, timeout: Duration) {
+        self.stop();
+        let deadline = tokio::time::Instant::now() + timeout;
+        for (name, task) in self.tasks {
+            let abort = task.abort_handle();
+            if tokio::time::timeout_at(deadline, task).await.is_err() {
+                tracing::warn!(job = name, "Background job did not stop in time");
+                abort.abort();
+            }
+        }
+    }
+}
+
+/// Completes when the process is asked to stop: SIGTERM (what Fly sends
+/// before a deploy replaces the machine) or Ctrl-C.
+pub async fn termination() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::warn!(error = %e, "Could not listen for SIGTERM"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
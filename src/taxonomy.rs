@@ -0,0 +1,521 @@
+use crate::error::ApiError;
+use crate::markdown::slugify;
+use crate::pagination::{Cursor, Page, PageQuery};
+use crate::services::{Post, POST_COLUMNS, PUBLISHED_FILTER};
+use crate::slugs;
+use crate::state::AppState;
+use crate::users::{AuthenticatedUser, Permission};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{FromRow, PgConnection};
+
+const MAX_TERMS_PER_POST: usize = 20;
+const MAX_TERM_LENGTH: usize = 100;
+
+/// The three taxonomies share one table shape (`id, slug, name, description`),
+/// so the admin CRUD endpoints are written once and dispatched on the path.
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Taxonomy {
+    Tags,
+    Categories,
+    Series,
+}
+
+impl Taxonomy {
+    fn table(self) -> &'static str {
+        match self {
+            Taxonomy::Tags => "tags",
+            Taxonomy::Categories => "categories",
+            Taxonomy::Series => "series",
+        }
+    }
+}
+
+#[derive(Serialize, FromRow)]
+struct Term {
+    id: i32,
+    slug: String,
+    name: String,
+    description: Option<String>,
+    post_count: i64,
+}
+
+#[derive(Serialize, FromRow)]
+struct SeriesEntry {
+    id: i32,
+    slug: String,
+    title: String,
+    published_date: NaiveDate,
+    series_position: i32,
+}
+
+#[derive(Serialize, FromRow)]
+struct SeriesLink {
+    id: i32,
+    slug: String,
+    title: String,
+}
+
+#[derive(Deserialize)]
+pub struct TermInput {
+    pub name: String,
+    pub slug: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TermUpdate {
+    pub name: Option<String>,
+    pub slug: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SeriesAssignment {
+    pub slug: String,
+    /// 1-based position within the series; appended to the end when omitted.
+    pub position: Option<i32>,
+}
+
+/// Lets an update distinguish an absent field (`None`) from an explicit
+/// `null` (`Some(None)`), which clears the value.
+pub fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+fn validate_terms(names: &[String]) -> Result<Vec<(String, String)>, &'static str> {
+    if names.len() > MAX_TERMS_PER_POST {
+        return Err("Too many tags or categories");
+    }
+    let mut terms: Vec<(String, String)> = Vec::with_capacity(names.len());
+    for name in names {
+        let name = name.trim();
+        let slug = slugify(name);
+        if slug.is_empty() || name.len() > MAX_TERM_LENGTH {
+            return Err("Invalid tag or category name");
+        }
+        if !terms.iter().any(|(existing, _)| *existing == slug) {
+            terms.push((slug, name.to_string()));
+        }
+    }
+    Ok(terms)
+}
+
+/// Validates tag and category names from a create/update request before any
+/// database work starts.
+pub fn validate_post_terms(
+    tags: Option<&Vec<String>>,
+    categories: Option<&Vec<String>>,
+) -> Result<(), &'static str> {
+    if let Some(tags) = tags {
+        validate_terms(tags)?;
+    }
+    if let Some(categories) = categories {
+        validate_terms(categories)?;
+    }
+    Ok(())
+}
+
+/// Replaces a post's tags, creating any that do not exist yet.
+pub async fn set_post_tags(
+    conn: &mut PgConnection,
+    post_id: i32,
+    names: &[String],
+) -> Result<(), sqlx::Error> {
+    set_post_terms(conn, ("tags", "post_tags", "tag_id"), post_id, names).await
+}
+
+/// Replaces a post's categories, creating any that do not exist yet.
+pub async fn set_post_categories(
+    conn: &mut PgConnection,
+    post_id: i32,
+    names: &[String],
+) -> Result<(), sqlx::Error> {
+    set_post_terms(conn, ("categories", "post_categories", "category_id"), post_id, names).await
+}
+
+async fn set_post_terms(
+    conn: &mut PgConnection,
+    (table, link_table, key): (&str, &str, &str),
+    post_id: i32,
+    names: &[String],
+) -> Result<(), sqlx::Error> {
+    // Callers run validate_post_terms first, so invalid names never get here.
+    let terms = validate_terms(names).unwrap_or_default();
+    let (slugs, labels): (Vec<String>, Vec<String>) = terms.into_iter().unzip();
+
+    sqlx::query(&format!("DELETE FROM {link_table} WHERE post_id = $1"))
+        .bind(post_id)
+        .execute(&mut *conn)
+        .await?;
+
+    if slugs.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(&format!(
+        "INSERT INTO {table} (slug, name) SELECT * FROM UNNEST($1::text[], $2::text[]) \
+         ON CONFLICT (slug) DO NOTHING"
+    ))
+    .bind(&slugs)
+    .bind(&labels)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(&format!(
+        "INSERT INTO {link_table} (post_id, {key}) SELECT $1, id FROM {table} WHERE slug = ANY($2)"
+    ))
+    .bind(post_id)
+    .bind(&slugs)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Moves a post into (or out of) a series. Returns `false` when the series
+/// slug does not exist.
+pub async fn set_post_series(
+    conn: &mut PgConnection,
+    post_id: i32,
+    assignment: Option<&SeriesAssignment>,
+) -> Result<bool, sqlx::Error> {
+    let assignment = match assignment {
+        Some(assignment) => assignment,
+        None => {
+            sqlx::query("UPDATE posts SET series_id = NULL, series_position = NULL WHERE id = $1")
+                .bind(post_id)
+                .execute(&mut *conn)
+                .await?;
+            return Ok(true);
+        }
+    };
+
+    let series_id: Option<i32> = sqlx::query_scalar("SELECT id FROM series WHERE slug = $1")
+        .bind(&assignment.slug)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let series_id = match series_id {
+        Some(series_id) => series_id,
+        None => return Ok(false),
+    };
+
+    // Leave the current slot first so positions can be shuffled without
+    // tripping the (series_id, series_position) uniqueness constraint.
+    sqlx::query("UPDATE posts SET series_id = NULL, series_position = NULL WHERE id = $1")
+        .bind(post_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let position = match assignment.position {
+        Some(position) => {
+            let position = position.max(1);
+            // Shifting in place would collide with the next row as each one
+            // is updated, since uniqueness is checked row by row. Moving the
+            // tail out to negative positions first avoids that.
+            sqlx::query(
+                "UPDATE posts SET series_position = -series_position \
+                 WHERE series_id = $1 AND series_position >= $2",
+            )
+            .bind(series_id)
+            .bind(position)
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query(
+                "UPDATE posts SET series_position = -series_position + 1 \
+                 WHERE series_id = $1 AND series_position < 0",
+            )
+            .bind(series_id)
+            .execute(&mut *conn)
+            .await?;
+            position
+        }
+        None => {
+            sqlx::query_scalar(
+                "SELECT COALESCE(MAX(series_position), 0) + 1 FROM posts WHERE series_id = $1",
+            )
+            .bind(series_id)
+            .fetch_one(&mut *conn)
+            .await?
+        }
+    };
+
+    sqlx::query("UPDATE posts SET series_id = $1, series_position = $2 WHERE id = $3")
+        .bind(series_id)
+        .bind(position)
+        .bind(post_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(true)
+}
+
+async fn list_terms(state: &AppState, taxonomy: Taxonomy) -> Result<HttpResponse, ApiError> {
+    let query = match taxonomy {
+        Taxonomy::Tags => format!(
+            "SELECT t.id, t.slug, t.name, t.description, COUNT(p.id) AS post_count FROM tags t \
+             LEFT JOIN post_tags l ON l.tag_id = t.id \
+             LEFT JOIN posts p ON p.id = l.post_id AND {PUBLISHED_FILTER} \
+             GROUP BY t.id ORDER BY t.name"
+        ),
+        Taxonomy::Categories => format!(
+            "SELECT t.id, t.slug, t.name, t.description, COUNT(p.id) AS post_count FROM categories t \
+             LEFT JOIN post_categories l ON l.category_id = t.id \
+             LEFT JOIN posts p ON p.id = l.post_id AND {PUBLISHED_FILTER} \
+             GROUP BY t.id ORDER BY t.name"
+        ),
+        Taxonomy::Series => format!(
+            "SELECT t.id, t.slug, t.name, t.description, COUNT(p.id) AS post_count FROM series t \
+             LEFT JOIN posts p ON p.series_id = t.id AND {PUBLISHED_FILTER} \
+             GROUP BY t.id ORDER BY t.name"
+        ),
+    };
+
+    let terms = sqlx::query_as::<_, Term>(&query).fetch_all(&state.db).await?;
+    Ok(HttpResponse::Ok().json(terms))
+}
+
+async fn posts_for_term(
+    req: &HttpRequest,
+    query: &PageQuery,
+    state: &AppState,
+    taxonomy: Taxonomy,
+    slug: &str,
+) -> Result<HttpResponse, ApiError> {
+    let (link_table, key, table) = match taxonomy {
+        Taxonomy::Tags => ("post_tags", "tag_id", "tags"),
+        _ => ("post_categories", "category_id", "categories"),
+    };
+    let cursor = query.cursor::<NaiveDate, i32>()?;
+    let limit = query.limit();
+    let (keyset, order) = Cursor::sql(cursor.as_ref(), "p.published_date", "p.id", 3);
+
+    sqlx::query_scalar::<_, i32>(&format!("SELECT id FROM {table} WHERE slug = $1"))
+        .bind(slug)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Not found"))?;
+
+    let sql = format!(
+        "SELECT {POST_COLUMNS} FROM posts p \
+         JOIN {link_table} l ON l.post_id = p.id JOIN {table} t ON t.id = l.{key} \
+         WHERE t.slug = $1 AND {PUBLISHED_FILTER} AND {keyset} \
+         ORDER BY {order} LIMIT $2"
+    );
+    let mut posts = sqlx::query_as::<_, Post>(&sql).bind(slug).bind(limit + 1);
+    if let Some(cursor) = &cursor {
+        posts = posts.bind(cursor.key).bind(cursor.id);
+    }
+
+    let posts = posts.fetch_all(&state.db).await?;
+    Ok(HttpResponse::Ok().json(Page::new(req, limit, cursor.as_ref(), posts, |post| {
+        (post.published_date, post.id)
+    })))
+}
+
+#[get("/tags")]
+pub async fn fetch_tags(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    list_terms(&data, Taxonomy::Tags).await
+}
+
+#[get("/tags/{slug}/posts")]
+pub async fn fetch_tag_posts(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    posts_for_term(&req, &query, &data, Taxonomy::Tags, &path.into_inner()).await
+}
+
+#[get("/categories")]
+pub async fn fetch_categories(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    list_terms(&data, Taxonomy::Categories).await
+}
+
+#[get("/categories/{slug}/posts")]
+pub async fn fetch_category_posts(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    posts_for_term(&req, &query, &data, Taxonomy::Categories, &path.into_inner()).await
+}
+
+#[get("/series")]
+pub async fn fetch_all_series(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    list_terms(&data, Taxonomy::Series).await
+}
+
+#[get("/series/{slug}")]
+pub async fn fetch_series(
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let slug = path.into_inner();
+
+    let (series_id, name, description) = sqlx::query_as::<_, (i32, String, Option<String>)>(
+        "SELECT id, name, description FROM series WHERE slug = $1",
+    )
+    .bind(&slug)
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("Series not found"))?;
+
+    let posts = sqlx::query_as::<_, SeriesEntry>(&format!(
+        "SELECT p.id, p.slug, p.title, p.published_date, p.series_position FROM posts p \
+         WHERE p.series_id = $1 AND {PUBLISHED_FILTER} ORDER BY p.series_position"
+    ))
+    .bind(series_id)
+    .fetch_all(&data.db)
+    .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "slug": slug,
+        "name": name,
+        "description": description,
+        "posts": posts
+    })))
+}
+
+/// Previous/next navigation for a post that belongs to a series. Unpublished
+/// parts are skipped so readers never land on a hidden post.
+#[get("/posts/{key}/series")]
+pub async fn fetch_post_series(
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let post_id = slugs::resolve_post(&data.db, &path.into_inner())
+        .await?
+        .ok_or_else(|| ApiError::not_found("Post not found"))?
+        .id;
+
+    let (series_id, position, slug, name) = sqlx::query_as::<_, (i32, i32, String, String)>(&format!(
+        "SELECT p.series_id, p.series_position, s.slug, s.name FROM posts p \
+         JOIN series s ON s.id = p.series_id WHERE p.id = $1 AND {PUBLISHED_FILTER}"
+    ))
+    .bind(post_id)
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("Post is not part of a series"))?;
+
+    let neighbour = |comparison: &str, order: &str| {
+        format!(
+            "SELECT p.id, p.slug, p.title FROM posts p WHERE p.series_id = $1 \
+             AND p.series_position {comparison} $2 AND {PUBLISHED_FILTER} \
+             ORDER BY p.series_position {order} LIMIT 1"
+        )
+    };
+    let previous = sqlx::query_as::<_, SeriesLink>(&neighbour("<", "DESC"))
+        .bind(series_id)
+        .bind(position)
+        .fetch_optional(&data.db)
+        .await?;
+    let next = sqlx::query_as::<_, SeriesLink>(&neighbour(">", "ASC"))
+        .bind(series_id)
+        .bind(position)
+        .fetch_optional(&data.db)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "series": { "slug": slug, "name": name },
+        "position": position,
+        "previous": previous,
+        "next": next
+    })))
+}
+
+const SLUG_EXISTS: &str = "Slug already exists";
+
+#[post("/admin/taxonomy/{kind}")]
+pub async fn create_term(
+    user: AuthenticatedUser,
+    path: web::Path<Taxonomy>,
+    body: web::Json<TermInput>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+
+    let taxonomy = path.into_inner();
+    let name = body.name.trim();
+    let slug = slugify(body.slug.as_deref().unwrap_or(name));
+    if slug.is_empty() || name.is_empty() || name.len() > MAX_TERM_LENGTH {
+        return Err(ApiError::invalid("Invalid name or slug"));
+    }
+
+    let id = sqlx::query_scalar::<_, i32>(&format!(
+        "INSERT INTO {} (slug, name, description) VALUES ($1, $2, $3) RETURNING id",
+        taxonomy.table()
+    ))
+    .bind(&slug)
+    .bind(name)
+    .bind(&body.description)
+    .fetch_one(&data.db)
+    .await
+    .map_err(ApiError::on_unique(SLUG_EXISTS))?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": id, "slug": slug })))
+}
+
+#[put("/admin/taxonomy/{kind}/{id}")]
+pub async fn update_term(
+    user: AuthenticatedUser,
+    path: web::Path<(Taxonomy, i32)>,
+    body: web::Json<TermUpdate>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+
+    let (taxonomy, id) = path.into_inner();
+    let name = body.name.as_deref().map(str::trim);
+    let slug = body.slug.as_deref().map(slugify);
+    if slug.as_deref() == Some("")
+        || name.is_some_and(|name| name.is_empty() || name.len() > MAX_TERM_LENGTH)
+    {
+        return Err(ApiError::invalid("Invalid name or slug"));
+    }
+
+    let result = sqlx::query(&format!(
+        "UPDATE {} SET name = COALESCE($1, name), slug = COALESCE($2, slug), \
+         description = COALESCE($3, description) WHERE id = $4",
+        taxonomy.table()
+    ))
+    .bind(name)
+    .bind(&slug)
+    .bind(&body.description)
+    .bind(id)
+    .execute(&data.db)
+    .await
+    .map_err(ApiError::on_unique(SLUG_EXISTS))?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("Not found"));
+    }
+    Ok(HttpResponse::Ok().json("Updated successfully"))
+}
+
+#[delete("/admin/taxonomy/{kind}/{id}")]
+pub async fn delete_term(
+    user: AuthenticatedUser,
+    path: web::Path<(Taxonomy, i32)>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManagePosts)?;
+
+    let (taxonomy, id) = path.into_inner();
+
+    // Links are removed by ON DELETE CASCADE; posts in a deleted series keep
+    // existing with series_id set to NULL.
+    let result = sqlx::query(&format!("DELETE FROM {} WHERE id = $1", taxonomy.table()))
+        .bind(id)
+        .execute(&data.db)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("Not found"));
+    }
+    Ok(HttpResponse::Ok().json("Deleted successfully"))
+}
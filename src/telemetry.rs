@@ -0,0 +1,185 @@
+use crate::config::{Config, LogFormat};
+use crate::error::ApiError;
+use crate::rate_limit::RateLimiter;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, USER_AGENT};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use rand::Rng;
+use std::io::IsTerminal;
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+const SERVICE_NAME: &str = "rayspace";
+
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Paths logged at debug rather than info: static assets and health probes
+/// would otherwise drown out everything else.
+const QUIET_PATHS: &[&str] = &["/styles/", "/images/", "/scripts/", "/healthz", "/readyz"];
+
+/// Flushes spans still waiting for the collector when dropped at the end of
+/// `main`.
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush traces: {e}");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber: `RUST_LOG` filtering (default `info`),
+/// text or JSON lines on stdout, and OTLP trace export when a collector is
+/// configured. `log` records from dependencies are forwarded as events, and
+/// sqlx reports every statement under `sqlx::query` (at debug, or warn when
+/// slow).
+pub fn init(config: &Config) -> Result<Telemetry, String> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let output = match config.logging.format {
+        LogFormat::Text => fmt::layer()
+            .with_ansi(std::io::stdout().is_terminal())
+            .boxed(),
+        // The span list carries the request id onto every event of a request.
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let provider = match &config.logging.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{endpoint}/v1/traces"))
+                .build()
+                .map_err(|e| format!("logging.otlp_endpoint: {e}"))?;
+            let resource = Resource::builder()
+                .with_service_name(SERVICE_NAME)
+                .with_attribute(KeyValue::new(
+                    "deployment.environment",
+                    config.environment.clone(),
+                ))
+                .build();
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(resource)
+                    .build(),
+            )
+        }
+        None => None,
+    };
+    let traces = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(traces)
+        .try_init()
+        .map_err(|e| e.to_string())?;
+    Ok(Telemetry { provider })
+}
+
+/// Client-supplied ids are kept so a request can be followed across a proxy,
+/// as long as they look like ids.
+fn incoming_request_id(req: &ServiceRequest) -> Option<String> {
+    let id = req.headers().get(&REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    valid.then(|| id.to_string())
+}
+
+/// Tags every request with an id, runs it inside a span carrying that id and
+/// logs it once it completes. [`ApiError`]s are turned into their final JSON
+/// body here; server errors are reported to Sentry here, and only here.
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = incoming_request_id(&req)
+        .unwrap_or_else(|| format!("{:016x}", rand::thread_rng().gen::<u64>()));
+    sentry::configure_scope(|scope| scope.set_tag("request_id", &id));
+
+    let client = req
+        .app_data::<web::Data<RateLimiter>>()
+        .and_then(|limiter| limiter.client_ip(req.headers(), req.peer_addr()));
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let span = tracing::info_span!(
+        "request",
+        otel.name = Empty,
+        otel.kind = "server",
+        request_id = %id,
+        http.request.method = %req.method(),
+        url.path = %req.path(),
+        http.route = Empty,
+        http.response.status_code = Empty,
+    );
+    let quiet = QUIET_PATHS.iter().any(|path| req.path().starts_with(path));
+    let started = Instant::now();
+
+    let res = next.call(req).instrument(span.clone()).await?;
+    let route = res.request().match_pattern().unwrap_or_default();
+    span.record(
+        "otel.name",
+        format!("{} {route}", res.request().method()).trim_end(),
+    );
+    span.record("http.route", route.as_str());
+    span.record("http.response.status_code", res.status().as_u16());
+
+    let rendered = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<ApiError>())
+        .map(|e| {
+            if e.status_code().is_server_error() {
+                sentry::capture_error(e);
+                tracing::error!(parent: &span, error = %e, "request failed");
+            }
+            e.render(Some(&id))
+        });
+    let mut res = match rendered {
+        Some(response) => res.into_response(response).map_into_right_body(),
+        None => res.map_into_left_body(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    let status = res.status().as_u16();
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let client = client.map(|ip| ip.to_string()).unwrap_or_default();
+    if quiet {
+        tracing::debug!(parent: &span, status, latency_ms, client, user_agent, "request completed");
+    } else {
+        tracing::info!(parent: &span, status, latency_ms, client, user_agent, "request completed");
+    }
+    Ok(res)
+}
@@ -0,0 +1,40 @@
+//! Setup shared by tests that need Postgres. They run against the database in
+//! `DATABASE_URL` (migrating it first) and are skipped when it isn't set.
+
+use crate::config::Config;
+use crate::spam::{Blocklist, SpamFilter};
+use crate::state::AppState;
+use crate::{migrations, spam};
+use actix_web::web;
+use sqlx::postgres::PgPoolOptions;
+
+pub async fn state() -> Option<web::Data<AppState>> {
+    dotenv::dotenv().ok();
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is not set; skipping");
+        return None;
+    };
+    let db = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&database_url)
+        .await
+        .expect("could not connect to DATABASE_URL");
+    migrations::run(&db)
+        .await
+        .expect("could not migrate the test database");
+    let spam_filter = SpamFilter::new(
+        Blocklist::empty(),
+        spam::DEFAULT_QUEUE_SCORE,
+        spam::DEFAULT_REJECT_SCORE,
+    );
+    Some(web::Data::new(AppState::new(
+        Config::for_tests(&database_url),
+        db,
+        spam_filter,
+    )))
+}
+
+/// A suffix that keeps rows created by concurrent test runs apart.
+pub fn unique() -> String {
+    format!("{}-{}", std::process::id(), rand::random::<u32>())
+}
@@ -0,0 +1,234 @@
+use crate::error::ApiError;
+use crate::pagination::{Cursor, Page, PageQuery};
+use crate::state::AppState;
+use actix_session::UserSession;
+use actix_web::dev::Payload;
+use actix_web::{get, put, web, FromRequest, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::future::Future;
+use std::pin::Pin;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Editor,
+    Moderator,
+    Banned,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Editor => "editor",
+            Role::Moderator => "moderator",
+            Role::Banned => "banned",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        match value {
+            "admin" => Some(Role::Admin),
+            "editor" => Some(Role::Editor),
+            "moderator" => Some(Role::Moderator),
+            "banned" => Some(Role::Banned),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    Comment,
+    ManagePosts,
+    ModerateComments,
+    ManageUsers,
+    ViewAnalytics,
+    ViewHealth,
+}
+
+impl Permission {
+    fn granted_by(self, role: Role) -> bool {
+        matches!(
+            (self, role),
+            (_, Role::Admin)
+                | (Permission::ManagePosts, Role::Editor)
+                | (Permission::ViewAnalytics, Role::Editor)
+                | (Permission::ModerateComments, Role::Moderator)
+        )
+    }
+}
+
+#[derive(Serialize, FromRow)]
+struct UserRow {
+    github_id: String,
+    name: String,
+    roles: Vec<String>,
+    created_at: DateTime<Utc>,
+    last_login: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateRoles {
+    pub roles: Vec<Role>,
+}
+
+/// The logged-in user, loaded from the `users` table on every request that
+/// asks for it so role changes take effect without a new login.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser {
+    pub id: String,
+    pub name: String,
+    pub roles: Vec<Role>,
+}
+
+impl AuthenticatedUser {
+    pub fn is_banned(&self) -> bool {
+        self.roles.contains(&Role::Banned)
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        if self.is_banned() {
+            return false;
+        }
+        permission == Permission::Comment
+            || self.roles.iter().any(|role| permission.granted_by(*role))
+    }
+
+    pub fn require(&self, permission: Permission) -> Result<(), ApiError> {
+        if self.can(permission) {
+            Ok(())
+        } else {
+            Err(ApiError::forbidden("Insufficient permissions"))
+        }
+    }
+
+    pub fn role_names(&self) -> Vec<&'static str> {
+        self.roles.iter().map(|role| role.as_str()).collect()
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let session = req.get_session();
+        let data = req.app_data::<web::Data<AppState>>().cloned();
+
+        Box::pin(async move {
+            let user_id = match session.get::<String>("user_id") {
+                Ok(Some(user_id)) => user_id,
+                _ => return Err(ApiError::Unauthorized),
+            };
+            let data = data.ok_or_else(|| ApiError::internal("AppState is not registered"))?;
+
+            match sqlx::query_as::<_, (String, Vec<String>)>(
+                "SELECT name, roles FROM users WHERE github_id = $1",
+            )
+            .bind(&user_id)
+            .fetch_optional(&data.db)
+            .await?
+            {
+                Some((name, roles)) => Ok(AuthenticatedUser {
+                    id: user_id,
+                    name,
+                    roles: roles.iter().filter_map(|role| Role::parse(role)).collect(),
+                }),
+                None => Err(ApiError::Unauthorized),
+            }
+        })
+    }
+}
+
+/// Records a login, creating the user on first sight. GitHub ids listed in
+/// `ADMIN_GITHUB_IDS` are granted the admin role when their account is created
+/// so a fresh database has an owner; after that, roles are only changed
+/// through [`update_user_roles`].
+#[tracing::instrument(skip_all, fields(github_id = %github_id))]
+pub async fn upsert_user(
+    state: &AppState,
+    github_id: &str,
+    name: &str,
+) -> Result<(), sqlx::Error> {
+    let bootstrap_roles: Vec<String> = if state.config.github.admin_ids.iter().any(|id| id == github_id) {
+        vec![Role::Admin.as_str().to_string()]
+    } else {
+        Vec::new()
+    };
+
+    sqlx::query(
+        "INSERT INTO users (github_id, name, roles, last_login) VALUES ($1, $2, $3, now()) \
+         ON CONFLICT (github_id) DO UPDATE SET name = EXCLUDED.name, last_login = now()",
+    )
+    .bind(github_id)
+    .bind(name)
+    .bind(&bootstrap_roles)
+    .execute(&state.db)
+    .await?;
+    Ok(())
+}
+
+/// Everyone who has logged in, most recent sign-ups first.
+#[get("/admin/users")]
+pub async fn list_users(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManageUsers)?;
+    let cursor = query.cursor::<DateTime<Utc>, String>()?;
+    let limit = query.limit();
+    let (keyset, order) = Cursor::sql(cursor.as_ref(), "created_at", "github_id", 2);
+
+    let sql = format!(
+        "SELECT github_id, name, roles, created_at, last_login FROM users \
+         WHERE {keyset} ORDER BY {order} LIMIT $1"
+    );
+    let mut users = sqlx::query_as::<_, UserRow>(&sql).bind(limit + 1);
+    if let Some(cursor) = &cursor {
+        users = users.bind(cursor.key).bind(&cursor.id);
+    }
+
+    let users = users.fetch_all(&data.db).await?;
+    Ok(HttpResponse::Ok().json(Page::new(
+        &req,
+        limit,
+        cursor.as_ref(),
+        users,
+        |user| (user.created_at, user.github_id.clone()),
+    )))
+}
+
+#[put("/admin/users/{id}/roles")]
+pub async fn update_user_roles(
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    body: web::Json<UpdateRoles>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(Permission::ManageUsers)?;
+
+    let github_id = path.into_inner();
+    if github_id == user.id && !body.roles.contains(&Role::Admin) {
+        return Err(ApiError::invalid("Admins cannot remove their own admin role"));
+    }
+
+    let mut roles: Vec<String> = body.roles.iter().map(|role| role.as_str().to_string()).collect();
+    roles.sort();
+    roles.dedup();
+
+    let result = sqlx::query("UPDATE users SET roles = $1 WHERE github_id = $2")
+        .bind(&roles)
+        .bind(&github_id)
+        .execute(&data.db)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("User not found"));
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "github_id": github_id, "roles": roles })))
+}
@@ -0,0 +1,128 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use sqlx::{Pool, Postgres};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Repeat views of a post by the same visitor inside this window count once.
+/// Visitor hashes change with the daily salt, so the window never spans more
+/// than the rest of the day.
+const DEDUP_WINDOW_MINUTES: i32 = 30;
+
+/// Lowercase user-agent fragments of crawlers, previewers and scripted clients.
+const BOT_AGENTS: &[&str] = &[
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "facebookexternalhit",
+    "embedly",
+    "preview",
+    "lighthouse",
+    "headless",
+    "phantomjs",
+    "curl/",
+    "wget/",
+    "httpie/",
+    "python-requests",
+    "python-urllib",
+    "aiohttp",
+    "go-http-client",
+    "java/",
+    "okhttp",
+    "libwww",
+    "scrapy",
+    "axios/",
+    "node-fetch",
+];
+
+/// Whether a user agent looks automated. Requests without one are treated as
+/// automated too; every browser sends it.
+pub fn is_bot(user_agent: Option<&str>) -> bool {
+    match user_agent.map(str::trim) {
+        None | Some("") => true,
+        Some(agent) => {
+            let agent = agent.to_lowercase();
+            BOT_AGENTS.iter().any(|fragment| agent.contains(fragment))
+        }
+    }
+}
+
+/// Today's salt, created on first use. Older salts are dropped when a new day
+/// starts so yesterday's hashes can no longer be linked to a visitor.
+pub async fn daily_salt(db: &Pool<Postgres>) -> Result<Vec<u8>, sqlx::Error> {
+    let mut salt = vec![0u8; 32];
+    rand::thread_rng().fill_bytes(&mut salt);
+
+    let (salt, created): (Vec<u8>, bool) = sqlx::query_as(
+        "INSERT INTO view_salts (day, salt) VALUES (CURRENT_DATE, $1) \
+         ON CONFLICT (day) DO UPDATE SET day = EXCLUDED.day \
+         RETURNING salt, (xmax = 0) AS created",
+    )
+    .bind(&salt)
+    .fetch_one(db)
+    .await?;
+
+    if created {
+        sqlx::query("DELETE FROM view_salts WHERE day < CURRENT_DATE")
+            .execute(db)
+            .await?;
+    }
+    Ok(salt)
+}
+
+/// Hashes whatever identifies a visitor (account or address plus user agent)
+/// with the daily salt. Only the hash is stored.
+pub fn visitor_hash(salt: &[u8], visitor: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(salt).expect("HMAC accepts keys of any length");
+    mac.update(visitor.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Records a view of `post_id` and bumps `posts.views` if this visitor hasn't
+/// viewed the post within the dedup window. Returns whether it was counted.
+pub async fn record_view(
+    db: &Pool<Postgres>,
+    post_id: i32,
+    visitor: &str,
+) -> Result<bool, sqlx::Error> {
+    let hash = visitor_hash(&daily_salt(db).await?, visitor);
+
+    let mut tx = db.begin().await?;
+    // Serializes concurrent requests from the same visitor so both can't pass
+    // the dedup check.
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(&hash)
+        .execute(&mut *tx)
+        .await?;
+    let recorded = sqlx::query(
+        "INSERT INTO post_views (post_id, visitor_hash) SELECT $1, $2 \
+         WHERE NOT EXISTS (SELECT 1 FROM post_views WHERE post_id = $1 AND visitor_hash = $2 \
+                           AND viewed_at > now() - make_interval(mins => $3))",
+    )
+    .bind(post_id)
+    .bind(&hash)
+    .bind(DEDUP_WINDOW_MINUTES)
+    .execute(&mut *tx)
+    .await?;
+
+    let counted = recorded.rows_affected() > 0;
+    if counted {
+        sqlx::query("UPDATE posts SET views = views + 1 WHERE id = $1")
+            .bind(post_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(counted)
+}
+
+/// Forgets views that are past the dedup window and were hashed with a salt
+/// that no longer exists; the count on the post is all that is kept.
+pub async fn prune_views(db: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM post_views WHERE viewed_at < now() - interval '1 day'")
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}